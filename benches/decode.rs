@@ -0,0 +1,91 @@
+use async_dup::{Arc, Mutex};
+use async_std::io::Cursor;
+use async_std::task;
+use bencher::{benchmark_group, benchmark_main, Bencher};
+
+const REQUEST: &[u8] = b"GET /assets/app.js?v=42 HTTP/1.1\r\n\
+Host: example.com\r\n\
+User-Agent: Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/115.0\r\n\
+Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\n\
+Accept-Language: en-US,en;q=0.5\r\n\
+Accept-Encoding: gzip, deflate, br\r\n\
+Connection: keep-alive\r\n\
+Cookie: session=0123456789abcdef0123456789abcdef; theme=dark\r\n\
+Cache-Control: max-age=0\r\n\
+\r\n";
+
+const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\n\
+Date: Sat, 17 Oct 2026 12:00:00 GMT\r\n\
+Server: example\r\n\
+Content-Type: application/javascript; charset=utf-8\r\n\
+Cache-Control: public, max-age=31536000, immutable\r\n\
+ETag: \"0123456789abcdef\"\r\n\
+Vary: Accept-Encoding\r\n\
+Content-Length: 0\r\n\
+\r\n";
+
+/// A message head followed by a body of many small NDJSON chunks.
+fn chunked(head: &[u8]) -> Vec<u8> {
+    let mut message = head.to_vec();
+    for i in 0..1000 {
+        let line = format!("{{\"id\":{}}}\n", i);
+        message.extend(format!("{:x}\r\n{}\r\n", line.len(), line).as_bytes());
+    }
+    message.extend(b"0\r\n\r\n");
+    message
+}
+
+fn server_decode(b: &mut Bencher) {
+    b.bytes = REQUEST.len() as u64;
+    b.iter(|| {
+        task::block_on(async {
+            let io = Arc::new(Mutex::new(Cursor::new(REQUEST.to_vec())));
+            async_h1::server::decode(io).await.unwrap().unwrap()
+        })
+    });
+}
+
+fn client_decode(b: &mut Bencher) {
+    b.bytes = RESPONSE.len() as u64;
+    b.iter(|| {
+        task::block_on(async {
+            let io = Cursor::new(RESPONSE.to_vec());
+            async_h1::client::decode(io).await.unwrap()
+        })
+    });
+}
+
+fn server_decode_chunked(b: &mut Bencher) {
+    let request = chunked(
+        b"POST /events HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\n\r\n",
+    );
+    b.bytes = request.len() as u64;
+    b.iter(|| {
+        task::block_on(async {
+            let io = Arc::new(Mutex::new(Cursor::new(request.clone())));
+            let (mut req, _) = async_h1::server::decode(io).await.unwrap().unwrap();
+            req.body_bytes().await.unwrap()
+        })
+    });
+}
+
+fn client_decode_chunked(b: &mut Bencher) {
+    let response = chunked(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+    b.bytes = response.len() as u64;
+    b.iter(|| {
+        task::block_on(async {
+            let io = Cursor::new(response.clone());
+            let mut res = async_h1::client::decode(io).await.unwrap();
+            res.body_bytes().await.unwrap()
+        })
+    });
+}
+
+benchmark_group!(
+    decode,
+    server_decode,
+    server_decode_chunked,
+    client_decode,
+    client_decode_chunked
+);
+benchmark_main!(decode);
@@ -0,0 +1,55 @@
+use async_std::io::{self, ReadExt};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task;
+use bencher::{benchmark_group, benchmark_main, Bencher};
+use http_types::{Body, Method, Response, StatusCode};
+
+const JSON: &str = r#"{"id":42,"name":"example","tags":["a","b","c"],"active":true}"#;
+
+/// A connected pair of loopback sockets, so writes are real syscalls.
+fn socket_pair() -> (TcpStream, TcpStream) {
+    task::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        server.set_nodelay(true).unwrap();
+        (server, client)
+    })
+}
+
+fn json_response() -> Response {
+    let mut res = Response::new(StatusCode::Ok);
+    res.insert_header("Cache-Control", "no-store");
+    res.set_body(Body::from_string(JSON.to_owned()));
+    res.set_content_type(http_types::mime::JSON);
+    res
+}
+
+/// Encode a response onto one socket with `write`, and read it off the other.
+fn bench_write<F>(b: &mut Bencher, write: F)
+where
+    F: Fn(async_h1::server::Encoder, &mut TcpStream) -> io::Result<u64>,
+{
+    let (mut server, mut client) = socket_pair();
+    let mut buf = vec![0; 64 * 1024];
+    b.iter(|| {
+        let encoder = async_h1::server::Encoder::new(json_response(), Method::Get);
+        let len = write(encoder, &mut server).unwrap() as usize;
+        task::block_on(client.read_exact(&mut buf[..len])).unwrap();
+    });
+}
+
+fn server_encode_copy(b: &mut Bencher) {
+    bench_write(b, |mut encoder, io| {
+        task::block_on(io::copy(&mut encoder, io))
+    });
+}
+
+fn server_encode_write_to(b: &mut Bencher) {
+    bench_write(b, |mut encoder, io| task::block_on(encoder.write_to(io)));
+}
+
+benchmark_group!(benches, server_encode_copy, server_encode_write_to);
+benchmark_main!(benches);
@@ -16,10 +16,12 @@ pub(crate) enum BodyEncoder {
 }
 
 impl BodyEncoder {
-    pub(crate) fn new(body: Body) -> Self {
+    /// Send a body of known length as is, and chunk a streaming one with
+    /// `chunked`.
+    pub(crate) fn new(body: Body, chunked: impl FnOnce(Body) -> ChunkedEncoder<Body>) -> Self {
         match body.len() {
             Some(_) => Self::Fixed(body),
-            None => Self::Chunked(ChunkedEncoder::new(body)),
+            None => Self::Chunked(chunked(body)),
         }
     }
 }
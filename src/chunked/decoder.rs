@@ -3,14 +3,16 @@ use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use async_std::io::{self, Read};
+use async_std::io::{self, BufRead, Read};
 use futures_core::ready;
 use http_types::trailers::{Sender, Trailers};
 
+use crate::{Error, ParseMode};
+
 /// Decodes a chunked body according to
 /// https://tools.ietf.org/html/rfc7230#section-4.1
 #[derive(Debug)]
-pub struct ChunkedDecoder<R: Read> {
+pub(crate) struct ChunkedDecoder<R: BufRead> {
     /// The underlying stream
     inner: R,
     /// Current state.
@@ -19,17 +21,49 @@ pub struct ChunkedDecoder<R: Read> {
     chunk_size: u64,
     /// Trailer channel sender.
     trailer_sender: Option<Sender>,
+    /// Total length of the chunks seen so far.
+    body_len: u64,
+    /// Maximum total length of the chunks.
+    max_body_len: Option<u64>,
+    /// How strictly trailers are parsed.
+    parse_mode: ParseMode,
 }
 
-impl<R: Read> ChunkedDecoder<R> {
+impl<R: BufRead> ChunkedDecoder<R> {
     pub(crate) fn new(inner: R, trailer_sender: Sender) -> Self {
         ChunkedDecoder {
             inner,
             state: State::ChunkSize,
             chunk_size: 0,
             trailer_sender: Some(trailer_sender),
+            body_len: 0,
+            max_body_len: None,
+            parse_mode: ParseMode::Strict,
         }
     }
+
+    /// Fail with [`Error::BodyTooLarge`] once the chunks add up to more than
+    /// `max_body_len` bytes.
+    pub(crate) fn with_max_body_len(mut self, max_body_len: Option<u64>) -> Self {
+        self.max_body_len = max_body_len;
+        self
+    }
+
+    /// Parse trailers according to `parse_mode`.
+    pub(crate) fn with_parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = parse_mode;
+        self
+    }
+
+    /// Unwrap the underlying stream.
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Whether the body was found to exceed the maximum length.
+    pub(crate) fn is_too_large(&self) -> bool {
+        matches!(self.state, State::TooLarge(_))
+    }
 }
 
 /// Decoder state.
@@ -50,6 +84,8 @@ enum State {
     TrailerSending(Pin<Box<dyn Future<Output = ()> + 'static + Send + Sync>>),
     /// All is said and done.
     Done,
+    /// The body exceeded the maximum length.
+    TooLarge(u64),
 }
 
 impl fmt::Debug for State {
@@ -63,17 +99,54 @@ impl fmt::Debug for State {
             State::Trailers(len, _) => write!(f, "State::Trailers({}, _)", len),
             State::TrailerSending(_) => write!(f, "State::TrailerSending"),
             State::Done => write!(f, "State::Done"),
+            State::TooLarge(max) => write!(f, "State::TooLarge({})", max),
         }
     }
 }
 
-impl<R: Read + Unpin> ChunkedDecoder<R> {
+impl<R: BufRead + Unpin> ChunkedDecoder<R> {
     fn poll_read_byte(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u8>> {
-        let mut byte = [0u8];
-        if ready!(Pin::new(&mut self.inner).poll_read(cx, &mut byte))? == 1 {
-            Poll::Ready(Ok(byte[0]))
-        } else {
-            eof()
+        let byte = match ready!(Pin::new(&mut self.inner).poll_fill_buf(cx))? {
+            [byte, ..] => *byte,
+            [] => return eof(),
+        };
+        Pin::new(&mut self.inner).consume(1);
+        Poll::Ready(Ok(byte))
+    }
+
+    /// Parse the hex digits of a chunk size out of the buffered bytes, up to
+    /// the CR that ends them.
+    fn poll_chunk_size(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let buf = ready!(Pin::new(&mut self.inner).poll_fill_buf(cx))?;
+            if buf.is_empty() {
+                return eof();
+            }
+            let mut used = 0;
+            let mut done = false;
+            for &byte in buf {
+                used += 1;
+                let digit = match byte {
+                    b'0'..=b'9' => byte - b'0',
+                    b'a'..=b'f' => 10 + byte - b'a',
+                    b'A'..=b'F' => 10 + byte - b'A',
+                    b'\r' => {
+                        done = true;
+                        break;
+                    }
+                    _ => return unexpected(byte, "hex digit or CR"),
+                };
+                self.chunk_size = self
+                    .chunk_size
+                    .checked_mul(16)
+                    .ok_or_else(overflow)?
+                    .checked_add(digit as u64)
+                    .ok_or_else(overflow)?;
+            }
+            Pin::new(&mut self.inner).consume(used);
+            if done {
+                return Poll::Ready(Ok(()));
+            }
         }
     }
 
@@ -91,6 +164,14 @@ impl<R: Read + Unpin> ChunkedDecoder<R> {
         }
     }
 
+    /// Account for `amt` bytes of chunk data having been read.
+    fn consume_chunk(&mut self, amt: usize) {
+        self.chunk_size -= amt as u64;
+        if self.chunk_size == 0 {
+            self.state = State::ChunkBodyExpectCr;
+        }
+    }
+
     fn send_trailers(&mut self, trailers: Trailers) {
         let sender = self
             .trailer_sender
@@ -101,6 +182,38 @@ impl<R: Read + Unpin> ChunkedDecoder<R> {
     }
 }
 
+/// Read the next line of the trailers out of the buffered bytes onto the end
+/// of `trailers`, returning whether a whole line was read.
+fn poll_trailer_line<R: BufRead + Unpin>(
+    mut reader: Pin<&mut R>,
+    cx: &mut Context<'_>,
+    len: &mut usize,
+    trailers: &mut [u8],
+) -> Poll<io::Result<bool>> {
+    loop {
+        let buf = ready!(reader.as_mut().poll_fill_buf(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(false));
+        }
+        // Only take the bytes up to the end of the line, so we don't read
+        // past the trailers into whatever follows the body.
+        let (line, done) = match buf.iter().position(|&b| b == b'\n') {
+            Some(i) => (&buf[..=i], true),
+            None => (buf, false),
+        };
+        let used = line.len();
+        if *len + used > trailers.len() {
+            return eof();
+        }
+        trailers[*len..*len + used].copy_from_slice(line);
+        *len += used;
+        reader.as_mut().consume(used);
+        if done {
+            return Poll::Ready(Ok(true));
+        }
+    }
+}
+
 fn eof<T>() -> Poll<io::Result<T>> {
     Poll::Ready(Err(io::Error::new(
         io::ErrorKind::UnexpectedEof,
@@ -119,96 +232,64 @@ fn overflow() -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, "Chunk size overflowed 64 bits")
 }
 
-impl<R: Read + Unpin> Read for ChunkedDecoder<R> {
-    #[allow(missing_doc_code_examples)]
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut [u8],
-    ) -> Poll<io::Result<usize>> {
-        let this = &mut *self;
-
+impl<R: BufRead + Unpin> ChunkedDecoder<R> {
+    /// Decode the framing up to the next chunk data, returning whether there
+    /// is any, or `false` once the body has ended.
+    fn poll_advance(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
         loop {
-            match this.state {
+            match self.state {
                 State::ChunkSize => {
-                    let byte = ready!(this.poll_read_byte(cx))?;
-                    let digit = match byte {
-                        b'0'..=b'9' => byte - b'0',
-                        b'a'..=b'f' => 10 + byte - b'a',
-                        b'A'..=b'F' => 10 + byte - b'A',
-                        b'\r' => {
-                            this.state = State::ChunkSizeExpectLf;
-                            continue;
-                        }
-                        _ => {
-                            return unexpected(byte, "hex digit or CR");
-                        }
-                    };
-                    this.chunk_size = this
-                        .chunk_size
-                        .checked_mul(16)
-                        .ok_or_else(overflow)?
-                        .checked_add(digit as u64)
-                        .ok_or_else(overflow)?;
+                    ready!(self.poll_chunk_size(cx))?;
+                    self.state = State::ChunkSizeExpectLf;
                 }
                 State::ChunkSizeExpectLf => {
-                    ready!(this.expect_byte(cx, b'\n', "LF"))?;
-                    if this.chunk_size == 0 {
-                        this.state = State::Trailers(0, Box::new([0u8; 8192]));
-                    } else {
-                        this.state = State::ChunkBody;
+                    ready!(self.expect_byte(cx, b'\n', "LF"))?;
+                    self.body_len = self.body_len.saturating_add(self.chunk_size);
+                    if let Some(max) = self.max_body_len {
+                        if self.body_len > max {
+                            self.state = State::TooLarge(max);
+                            continue;
+                        }
                     }
-                }
-                State::ChunkBody => {
-                    let max_bytes = std::cmp::min(
-                        buf.len(),
-                        std::cmp::min(this.chunk_size, usize::MAX as u64) as usize,
-                    );
-                    let bytes_read =
-                        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf[..max_bytes]))?;
-                    this.chunk_size -= bytes_read as u64;
-                    if bytes_read == 0 {
-                        return eof();
-                    } else if this.chunk_size == 0 {
-                        this.state = State::ChunkBodyExpectCr;
+                    if self.chunk_size == 0 {
+                        self.state = State::Trailers(0, Box::new([0u8; 8192]));
+                    } else {
+                        self.state = State::ChunkBody;
                     }
-                    return Poll::Ready(Ok(bytes_read));
                 }
+                State::ChunkBody => return Poll::Ready(Ok(true)),
                 State::ChunkBodyExpectCr => {
-                    ready!(this.expect_byte(cx, b'\r', "CR"))?;
-                    this.state = State::ChunkBodyExpectLf;
+                    ready!(self.expect_byte(cx, b'\r', "CR"))?;
+                    self.state = State::ChunkBodyExpectLf;
                 }
                 State::ChunkBodyExpectLf => {
-                    ready!(this.expect_byte(cx, b'\n', "LF"))?;
-                    this.state = State::ChunkSize;
+                    ready!(self.expect_byte(cx, b'\n', "LF"))?;
+                    self.state = State::ChunkSize;
                 }
                 State::Trailers(ref mut len, ref mut buf) => {
-                    let bytes_read =
-                        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf[*len..]))?;
-                    *len += bytes_read;
+                    let inner = Pin::new(&mut self.inner);
+                    let line_read = ready!(poll_trailer_line(inner, cx, len, &mut buf[..]))?;
                     let len = *len;
-                    if len == 0 {
-                        this.send_trailers(Trailers::new());
-                        continue;
-                    }
-                    if bytes_read == 0 {
+                    if !line_read {
+                        if len == 0 {
+                            self.send_trailers(Trailers::new());
+                            continue;
+                        }
                         return eof();
                     }
+                    let trailers = self
+                        .parse_mode
+                        .normalize_trailers(&buf[..len])
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
                     let mut headers = [httparse::EMPTY_HEADER; 16];
-                    let parse_result = httparse::parse_headers(&buf[..len], &mut headers)
+                    let parse_result = httparse::parse_headers(&trailers, &mut headers)
                         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
                     use httparse::Status;
                     match parse_result {
-                        Status::Partial => {
-                            if len == buf.len() {
-                                return eof();
-                            } else {
-                                return Poll::Pending;
-                            }
-                        }
+                        Status::Partial => continue,
                         Status::Complete((offset, headers)) => {
-                            if offset != len {
-                                return unexpected(buf[offset], "end of trailers");
+                            if offset != trailers.len() {
+                                return unexpected(trailers[offset], "end of trailers");
                             }
                             let mut trailers = Trailers::new();
                             for header in headers {
@@ -217,20 +298,77 @@ impl<R: Read + Unpin> Read for ChunkedDecoder<R> {
                                     String::from_utf8_lossy(header.value).as_ref(),
                                 );
                             }
-                            this.send_trailers(trailers);
+                            self.send_trailers(trailers);
                         }
                     }
                 }
                 State::TrailerSending(ref mut fut) => {
-                    let _ = ready!(Pin::new(fut).poll(cx));
-                    this.state = State::Done;
+                    ready!(Pin::new(fut).poll(cx));
+                    self.state = State::Done;
+                }
+                State::Done => return Poll::Ready(Ok(false)),
+                State::TooLarge(max) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        Error::BodyTooLarge(max),
+                    )))
                 }
-                State::Done => return Poll::Ready(Ok(0)),
             }
         }
     }
 }
 
+impl<R: BufRead + Unpin> Read for ChunkedDecoder<R> {
+    #[allow(missing_doc_code_examples)]
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if !ready!(this.poll_advance(cx))? {
+            return Poll::Ready(Ok(0));
+        }
+        let max_bytes = std::cmp::min(
+            buf.len(),
+            std::cmp::min(this.chunk_size, usize::MAX as u64) as usize,
+        );
+        let bytes_read = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf[..max_bytes]))?;
+        if bytes_read == 0 && max_bytes > 0 {
+            return eof();
+        }
+        this.consume_chunk(bytes_read);
+        Poll::Ready(Ok(bytes_read))
+    }
+}
+
+impl<R: BufRead + Unpin> BufRead for ChunkedDecoder<R> {
+    /// Return the buffered data of the current chunk, borrowed straight from
+    /// the inner reader.
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if !ready!(this.poll_advance(cx))? {
+            return Poll::Ready(Ok(&[]));
+        }
+        let chunk_size = this.chunk_size;
+        let buf = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+        if buf.is_empty() {
+            return eof();
+        }
+        let len = std::cmp::min(buf.len() as u64, chunk_size) as usize;
+        Poll::Ready(Ok(&buf[..len]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        if let State::ChunkBody = this.state {
+            let amt = std::cmp::min(amt as u64, this.chunk_size) as usize;
+            Pin::new(&mut this.inner).consume(amt);
+            this.consume_chunk(amt);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -343,4 +481,78 @@ mod tests {
             );
         });
     }
+
+    #[test]
+    fn test_chunked_small_buffer() {
+        async_std::task::block_on(async move {
+            let input = async_std::io::Cursor::new(
+                "4\r\n\
+                 Wiki\r\n\
+                 5\r\n\
+                 pedia\r\n\
+                 0\r\n\
+                 Expires: Wed, 21 Oct 2015 07:28:00 GMT\r\n\
+                 \r\n\
+                 GET"
+                .as_bytes(),
+            );
+            // Chunk sizes, CRLFs and trailers all straddle buffer boundaries.
+            let input = async_std::io::BufReader::with_capacity(3, input);
+            let (s, r) = async_channel::bounded(1);
+            let sender = Sender::new(s);
+            let mut decoder = ChunkedDecoder::new(input, sender);
+
+            let mut output = String::new();
+            decoder.read_to_string(&mut output).await.unwrap();
+            assert_eq!(output, "Wikipedia");
+
+            let trailers = r.recv().await.unwrap();
+            assert_eq!(trailers["Expires"], "Wed, 21 Oct 2015 07:28:00 GMT");
+
+            // Nothing past the trailers is read.
+            let mut rest = String::new();
+            decoder
+                .into_inner()
+                .read_to_string(&mut rest)
+                .await
+                .unwrap();
+            assert_eq!(rest, "GET");
+        });
+    }
+
+    async fn fill_buf<R: BufRead + Unpin>(reader: &mut R) -> Vec<u8> {
+        async_std::future::poll_fn(|cx| {
+            Pin::new(&mut *reader)
+                .poll_fill_buf(cx)
+                .map(|buf| buf.unwrap().to_vec())
+        })
+        .await
+    }
+
+    #[test]
+    fn test_chunked_buf_read() {
+        async_std::task::block_on(async move {
+            let input = async_std::io::Cursor::new(
+                "4\r\n\
+                 Wiki\r\n\
+                 5\r\n\
+                 pedia\r\n\
+                 0\r\n\
+                 \r\n"
+                    .as_bytes(),
+            );
+            let (s, _r) = async_channel::bounded(1);
+            let sender = Sender::new(s);
+            let mut decoder = ChunkedDecoder::new(input, sender);
+
+            // Only the data of the current chunk is borrowed from the buffer.
+            assert_eq!(fill_buf(&mut decoder).await, b"Wiki");
+            Pin::new(&mut decoder).consume(2);
+            assert_eq!(fill_buf(&mut decoder).await, b"ki");
+            Pin::new(&mut decoder).consume(2);
+            assert_eq!(fill_buf(&mut decoder).await, b"pedia");
+            Pin::new(&mut decoder).consume(5);
+            assert_eq!(fill_buf(&mut decoder).await, b"");
+        });
+    }
 }
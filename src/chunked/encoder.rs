@@ -1,15 +1,60 @@
+use std::future::Future;
+use std::io::Write;
 use std::pin::Pin;
 
 use async_std::io;
 use async_std::io::prelude::*;
+use async_std::io::Cursor;
 use async_std::task::{Context, Poll};
 use futures_core::ready;
+use http_types::headers::{
+    HeaderName, HeaderValues, CONTENT_LENGTH, HOST, TRAILER, TRANSFER_ENCODING,
+};
+use http_types::trailers::{Receiver, Trailers};
+
+/// The default target size of a chunk's data.
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 8 * 1024;
+
+/// The largest chunk size that can be configured.
+pub(crate) const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
 
 /// An encoder for chunked encoding.
+///
+/// Each chunk is written out across as many reads as the caller's buffer
+/// size requires, so any buffer size works.
 #[derive(Debug)]
 pub(crate) struct ChunkedEncoder<R> {
     reader: R,
-    done: bool,
+    state: State,
+    /// The data of the chunk being read or written.
+    chunk: Vec<u8>,
+    chunk_size: usize,
+    coalesce: bool,
+    body_done: bool,
+    trailers: Option<PendingTrailers>,
+}
+
+/// The trailers sent after the last chunk.
+#[derive(Debug)]
+pub(crate) enum PendingTrailers {
+    /// Still to be received on the trailer channel.
+    Receiving(Receiver),
+    /// Received before the body was encoded.
+    Received(Option<Trailers>),
+}
+
+/// Encoder state.
+#[derive(Debug)]
+enum State {
+    /// Reading the data of the next chunk from the body.
+    Reading,
+    /// Writing the chunk's size line, its `len` bytes of data and a CRLF,
+    /// `pos` bytes in.
+    Chunk { len: usize, pos: usize },
+    /// The body has ended, waiting for the trailers.
+    Trailers,
+    /// Writing the last chunk and the trailer section.
+    LastChunk(Cursor<Vec<u8>>),
 }
 
 impl<R: Read + Unpin> ChunkedEncoder<R> {
@@ -17,9 +62,72 @@ impl<R: Read + Unpin> ChunkedEncoder<R> {
     pub(crate) fn new(reader: R) -> Self {
         Self {
             reader,
-            done: false,
+            state: State::Reading,
+            chunk: Vec::new(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            coalesce: false,
+            body_done: false,
+            trailers: None,
         }
     }
+
+    /// Send `trailers` after the last chunk.
+    pub(crate) fn with_trailers(mut self, trailers: PendingTrailers) -> Self {
+        self.trailers = Some(trailers);
+        self
+    }
+
+    /// Set the maximum size of a chunk's data, clamped to between 1 byte and
+    /// `MAX_CHUNK_SIZE`.
+    pub(crate) fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        self
+    }
+
+    /// Set whether body reads that are ready at once are combined into a
+    /// single chunk, up to the chunk size.
+    pub(crate) fn with_coalescing(mut self, coalesce: bool) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// Read the data of the next chunk from the body, returning its length.
+    /// Returns 0 at the end of the body.
+    ///
+    /// The chunk buffer starts small and doubles whenever a read fills it, up
+    /// to the chunk size, so large chunk sizes only cost memory for bodies
+    /// that make use of them.
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        if self.chunk.is_empty() {
+            let len = std::cmp::min(self.chunk_size, DEFAULT_CHUNK_SIZE);
+            self.chunk.resize(len, 0);
+        }
+        let mut filled = 0;
+        loop {
+            let reader = Pin::new(&mut self.reader);
+            match reader.poll_read(cx, &mut self.chunk[filled..]) {
+                Poll::Ready(Ok(0)) => {
+                    self.body_done = true;
+                    break;
+                }
+                Poll::Ready(Ok(n)) => {
+                    filled += n;
+                    if filled == self.chunk.len() && filled < self.chunk_size {
+                        let len = std::cmp::min(filled.saturating_mul(2), self.chunk_size);
+                        self.chunk.resize(len, 0);
+                    }
+                    if !self.coalesce || filled == self.chunk_size {
+                        break;
+                    }
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                // Send what we have rather than wait for more.
+                Poll::Pending if filled > 0 => break,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(filled))
+    }
 }
 
 impl<R: Read + Unpin> Read for ChunkedEncoder<R> {
@@ -28,91 +136,103 @@ impl<R: Read + Unpin> Read for ChunkedEncoder<R> {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        if self.done {
+        if buf.is_empty() {
             return Poll::Ready(Ok(0));
         }
-        let reader = &mut self.reader;
-
-        let max_bytes_to_read = max_bytes_to_read(buf.len());
-
-        let bytes = ready!(Pin::new(reader).poll_read(cx, &mut buf[..max_bytes_to_read]))?;
-        if bytes == 0 {
-            self.done = true;
+        let this = &mut *self;
+        loop {
+            match this.state {
+                State::Reading if this.body_done => this.state = State::Trailers,
+                State::Reading => {
+                    let len = ready!(this.poll_chunk(cx))?;
+                    if len > 0 {
+                        this.state = State::Chunk { len, pos: 0 };
+                    }
+                }
+                State::Chunk { len, ref mut pos } => {
+                    let (size_line, size_line_len) = size_line(len);
+                    let segments = [&size_line[..size_line_len], &this.chunk[..len], b"\r\n"];
+                    let written = write_segments(&segments, pos, buf);
+                    if *pos == size_line_len + len + 2 {
+                        this.state = State::Reading;
+                    }
+                    return Poll::Ready(Ok(written));
+                }
+                State::Trailers => {
+                    // The body has ended, wait for the trailers to end the message.
+                    let trailers = match &mut this.trailers {
+                        Some(PendingTrailers::Receiving(receiver)) => {
+                            ready!(Pin::new(receiver).poll(cx))
+                        }
+                        Some(PendingTrailers::Received(trailers)) => trailers.take(),
+                        None => None,
+                    };
+                    this.chunk = Vec::new();
+                    this.state = State::LastChunk(Cursor::new(encode_last_chunk(trailers)));
+                }
+                State::LastChunk(ref mut last_chunk) => {
+                    return Pin::new(last_chunk).poll_read(cx, buf);
+                }
+            }
         }
-        let start = format!("{:X}\r\n", bytes);
-        let start_length = start.as_bytes().len();
-        let total = bytes + start_length + 2;
-        buf.copy_within(..bytes, start_length);
-        buf[..start_length].copy_from_slice(start.as_bytes());
-        buf[total - 2..total].copy_from_slice(b"\r\n");
-        Poll::Ready(Ok(total))
     }
 }
 
-fn max_bytes_to_read(buf_len: usize) -> usize {
-    if buf_len < 6 {
-        // the minimum read size is of 6 represents one byte of
-        // content from the body. the other five bytes are 1\r\n_\r\n
-        // where _ is the actual content in question
-        panic!("buffers of length {} are too small for this implementation. if this is a problem for you, please open an issue", buf_len);
-    }
-
-    let bytes_remaining_after_two_cr_lns = (buf_len - 4) as f64;
-
-    // the maximum number of bytes that the hex representation of remaining bytes might take
-    let max_bytes_of_hex_framing = bytes_remaining_after_two_cr_lns.log2() / 4f64;
+/// Format the size line of a chunk of `len` bytes.
+fn size_line(len: usize) -> ([u8; 18], usize) {
+    let mut line = [0; 18];
+    let mut rest = &mut line[..];
+    write!(rest, "{:X}\r\n", len).expect("a usize fits in 16 hex digits");
+    let written = 18 - rest.len();
+    (line, written)
+}
 
-    (bytes_remaining_after_two_cr_lns - max_bytes_of_hex_framing.ceil()) as usize
+/// Copy as much of `segments` as fits into `buf`, starting `pos` bytes in,
+/// and advance `pos` past what was copied.
+fn write_segments(segments: &[&[u8]], pos: &mut usize, buf: &mut [u8]) -> usize {
+    let mut written = 0;
+    let mut offset = 0;
+    for segment in segments {
+        if written == buf.len() {
+            break;
+        }
+        if *pos < offset + segment.len() {
+            let start = *pos - offset;
+            let len = (segment.len() - start).min(buf.len() - written);
+            buf[written..written + len].copy_from_slice(&segment[start..start + len]);
+            written += len;
+            *pos += len;
+        }
+        offset += segment.len();
+    }
+    written
 }
 
-#[cfg(test)]
-mod test_bytes_to_read {
-    #[test]
-    fn simple_check_of_known_values() {
-        // the marked rows are the most important part of this test,
-        // and a nonobvious but intentional consequence of the
-        // implementation. in order to avoid overflowing, we must use
-        // one fewer than the available buffer bytes because
-        // increasing the read size increase the number of framed
-        // bytes by two. This occurs when the hex representation of
-        // the content bytes is near an increase in order of magnitude
-        // (F->10, FF->100, FFF-> 1000, etc)
-        let values = vec![
-            (6, 1),       // 1
-            (7, 2),       // 2
-            (20, 15),     // F
-            (21, 15),     // F <-
-            (22, 16),     // 10
-            (23, 17),     // 11
-            (260, 254),   // FE
-            (261, 254),   // FE <-
-            (262, 255),   // FF <-
-            (263, 256),   // 100
-            (4100, 4093), // FFD
-            (4101, 4093), // FFD <-
-            (4102, 4094), // FFE <-
-            (4103, 4095), // FFF <-
-            (4104, 4096), // 1000
-        ];
-
-        for (input, expected) in values {
-            let actual = super::max_bytes_to_read(input);
-            assert_eq!(
-                actual, expected,
-                "\n\nexpected max_bytes_to_read({}) to be {}, but it was {}",
-                input, expected, actual
-            );
-
-            // testing the test:
-            let used_bytes = expected + 4 + format!("{:X}", expected).len();
-            assert!(
-                used_bytes == input || used_bytes == input - 1,
-                "\n\nfor an input of {}, expected used bytes to be {} or {}, but was {}",
-                input,
-                input,
-                input - 1,
-                used_bytes
-            );
+/// Encode the last chunk, followed by the trailer section.
+///
+/// Fields that frame the message are left out, since they aren't allowed in
+/// trailers.
+///
+/// https://tools.ietf.org/html/rfc7230#section-4.1.2
+fn encode_last_chunk(trailers: Option<Trailers>) -> Vec<u8> {
+    let mut last_chunk = b"0\r\n".to_vec();
+    if let Some(trailers) = &trailers {
+        for (name, values) in trailer_fields(trailers) {
+            for value in values.iter() {
+                last_chunk.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+            }
         }
     }
+    last_chunk.extend_from_slice(b"\r\n");
+    last_chunk
+}
+
+/// The fields of `trailers` that can be sent, sorted by name.
+pub(crate) fn trailer_fields(trailers: &Trailers) -> Vec<(&HeaderName, &HeaderValues)> {
+    let mut fields = trailers
+        .iter()
+        .filter(|(name, _)| ![CONTENT_LENGTH, HOST, TRAILER, TRANSFER_ENCODING].contains(name))
+        .collect::<Vec<_>>();
+    fields.sort_unstable_by_key(|(name, _)| name.as_str());
+    fields
 }
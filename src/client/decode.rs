@@ -1,4 +1,5 @@
-use async_std::io::{BufReader, Read};
+use async_std::future;
+use async_std::io::Read;
 use async_std::prelude::*;
 use http_types::{ensure, ensure_eq, format_err};
 use http_types::{
@@ -8,70 +9,42 @@ use http_types::{
 
 use std::convert::TryFrom;
 
+use super::ClientOptions;
 use crate::chunked::ChunkedDecoder;
-use crate::date::fmt_http_date;
-use crate::{MAX_HEADERS, MAX_HEAD_LENGTH};
-
-const CR: u8 = b'\r';
-const LF: u8 = b'\n';
+use crate::date::http_date_now;
+use crate::parse::HeadParser;
+use crate::read_buffer::ReadBuffer;
 
 /// Decode an HTTP response on the client.
 pub async fn decode<R>(reader: R) -> http_types::Result<Response>
 where
     R: Read + Unpin + Send + Sync + 'static,
 {
-    let mut reader = BufReader::new(reader);
-    let mut buf = Vec::new();
-    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
-    let mut httparse_res = httparse::Response::new(&mut headers);
-
-    // Keep reading bytes from the stream until we hit the end of the stream.
-    loop {
-        let bytes_read = reader.read_until(LF, &mut buf).await?;
-        // No more bytes are yielded from the stream.
-
-        match (bytes_read, buf.len()) {
-            (0, 0) => return Err(format_err!("connection closed")),
-            (0, _) => return Err(format_err!("empty response")),
-            _ => {}
-        }
-
-        // Prevent CWE-400 DDOS with large HTTP Headers.
-        ensure!(
-            buf.len() < MAX_HEAD_LENGTH,
-            "Head byte length should be less than 8kb"
-        );
-
-        // We've hit the end delimiter of the stream.
-        let idx = buf.len() - 1;
-        if idx >= 3 && buf[idx - 3..=idx] == [CR, LF, CR, LF] {
-            break;
-        }
-        if idx >= 1 && buf[idx - 1..=idx] == [LF, LF] {
-            break;
-        }
-    }
-
-    // Convert our header buf into an httparse instance, and validate.
-    let status = httparse_res.parse(&buf)?;
-    ensure!(!status.is_partial(), "Malformed HTTP head");
-
-    let code = httparse_res.code;
-    let code = code.ok_or_else(|| format_err!("No status code found"))?;
-
-    // Convert httparse headers + body into a `http_types::Response` type.
-    let version = httparse_res.version;
-    let version = version.ok_or_else(|| format_err!("No version found"))?;
-    ensure_eq!(version, 1, "Unsupported HTTP version");
+    decode_with_opts(reader, &ClientOptions::default()).await
+}
 
-    let mut res = Response::new(StatusCode::try_from(code)?);
-    for header in httparse_res.headers.iter() {
-        res.append_header(header.name, std::str::from_utf8(header.value)?);
-    }
+/// Decode an HTTP response on the client, using the limits in `opts`.
+pub async fn decode_with_opts<R>(reader: R, opts: &ClientOptions) -> http_types::Result<Response>
+where
+    R: Read + Unpin + Send + Sync + 'static,
+{
+    let mut reader = ReadBuffer::new(reader);
+
+    // Parse the head straight out of the read buffer, reading more until it's
+    // complete.
+    let limit = opts.max_head_length.saturating_add(1);
+    let mut parsed = false;
+    let mut parser = opts.head_parser();
+    let mut parse = |buf: &[u8]| parse_head(&mut parser, buf);
+    let head = future::poll_fn(|cx| reader.poll_head(cx, limit, &mut parsed, &mut parse)).await?;
+    let mut res = match head {
+        Some(res) => res,
+        None if reader.buffer().is_empty() => return Err(format_err!("connection closed")),
+        None => return Err(format_err!("empty response")),
+    };
 
     if res.header(DATE).is_none() {
-        let date = fmt_http_date(std::time::SystemTime::now());
-        res.insert_header(DATE, &format!("date: {}\r\n", date)[..]);
+        res.insert_header(DATE, http_date_now());
     }
 
     let content_length = res.header(CONTENT_LENGTH);
@@ -85,8 +58,9 @@ where
     if let Some(encoding) = transfer_encoding {
         if encoding.last().as_str() == "chunked" {
             let trailers_sender = res.send_trailers();
-            let reader = BufReader::new(ChunkedDecoder::new(reader, trailers_sender));
-            res.set_body(Body::from_reader(reader, None));
+            let decoder =
+                ChunkedDecoder::new(reader, trailers_sender).with_parse_mode(opts.parse_mode);
+            res.set_body(Body::from_reader(decoder, None));
 
             // Return the response.
             return Ok(res);
@@ -102,3 +76,41 @@ where
     // Return the response.
     Ok(res)
 }
+
+/// Parse a response head out of the start of `buf`, which may also hold bytes
+/// past the end of the head.
+///
+/// Returns `None` if `buf` doesn't hold the whole head yet, or else the
+/// response and the length of its head.
+fn parse_head(
+    parser: &mut HeadParser,
+    buf: &[u8],
+) -> http_types::Result<Option<(Response, usize)>> {
+    parser.parse(buf, |config, buf, headers| {
+        let mut httparse_res = httparse::Response::new(headers);
+        Ok(match config.parse_response(&mut httparse_res, buf)? {
+            httparse::Status::Complete(len) => {
+                httparse::Status::Complete((len, response_from_httparse(&httparse_res)))
+            }
+            httparse::Status::Partial => httparse::Status::Partial,
+        })
+    })
+}
+
+/// Build a response, without its body, from a parsed head.
+fn response_from_httparse(
+    httparse_res: &httparse::Response<'_, '_>,
+) -> http_types::Result<Response> {
+    let code = httparse_res.code;
+    let code = code.ok_or_else(|| format_err!("No status code found"))?;
+
+    let version = httparse_res.version;
+    let version = version.ok_or_else(|| format_err!("No version found"))?;
+    ensure_eq!(version, 1, "Unsupported HTTP version");
+
+    let mut res = Response::new(StatusCode::try_from(code)?);
+    for header in httparse_res.headers.iter() {
+        res.append_header(header.name, std::str::from_utf8(header.value)?);
+    }
+    Ok(res)
+}
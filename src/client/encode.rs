@@ -4,9 +4,12 @@ use std::pin::Pin;
 use async_std::io::{self, Cursor, Read};
 use async_std::task::{Context, Poll};
 use http_types::headers::{CONTENT_LENGTH, HOST, TRANSFER_ENCODING};
-use http_types::{Method, Request};
+use http_types::trailers::Receiver;
+use http_types::{Body, Method, Request};
 
 use crate::body_encoder::BodyEncoder;
+use crate::chunked::{ChunkedEncoder, PendingTrailers, DEFAULT_CHUNK_SIZE};
+use crate::corked_write::write_corked;
 use crate::read_to_end;
 use crate::EncoderState;
 
@@ -16,17 +19,84 @@ use crate::EncoderState;
 pub struct Encoder {
     request: Request,
     state: EncoderState,
+    trailers: Option<Receiver>,
+    chunk_size: usize,
+    coalesce_chunks: bool,
 }
 
 impl Encoder {
     /// build a new client encoder
+    ///
+    /// Trailers sent through the request's trailer channel are written after
+    /// the last chunk, so requests with trailers are always sent chunked.
     pub fn new(request: Request) -> Self {
         Self {
             request,
             state: EncoderState::Start,
+            trailers: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            coalesce_chunks: false,
         }
     }
 
+    /// Set the maximum size of the data in each chunk of a chunked body.
+    /// Defaults to 8 KiB.
+    ///
+    /// Sizes are clamped to between 1 byte and 16 MiB.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Set whether body reads that complete immediately one after another are
+    /// combined into a single chunk, up to the chunk size. Defaults to false.
+    pub fn with_chunk_coalescing(mut self, coalesce_chunks: bool) -> Self {
+        self.coalesce_chunks = coalesce_chunks;
+        self
+    }
+
+    /// Write the encoded request straight to `writer`, returning the number
+    /// of bytes written.
+    ///
+    /// The head is written together with the first bytes of the body using
+    /// vectored writes, so a request with a small body is sent in a single
+    /// write. The writer is flushed at the end.
+    pub async fn write_to<W>(&mut self, writer: &mut W) -> io::Result<u64>
+    where
+        W: io::Write + Unpin + ?Sized,
+    {
+        if let EncoderState::Start = self.state {
+            let head = self.compute_head()?.into_inner();
+            self.state = self.body_state();
+            write_corked(&head, self, writer).await
+        } else {
+            io::copy(self, writer).await
+        }
+    }
+
+    /// The state after the head has been written.
+    fn body_state(&mut self) -> EncoderState {
+        let body = self.request.take_body();
+        match self.trailers.take() {
+            Some(trailers) => {
+                let trailers = PendingTrailers::Receiving(trailers);
+                let encoder = self.chunked(body).with_trailers(trailers);
+                EncoderState::Body(Box::new(BodyEncoder::Chunked(encoder)), 0, None)
+            }
+            None => {
+                let req_len = body.len();
+                let encoder = BodyEncoder::new(body, |body| self.chunked(body));
+                EncoderState::Body(Box::new(encoder), 0, req_len)
+            }
+        }
+    }
+
+    fn chunked(&self, body: Body) -> ChunkedEncoder<Body> {
+        ChunkedEncoder::new(body)
+            .with_chunk_size(self.chunk_size)
+            .with_coalescing(self.coalesce_chunks)
+    }
+
     fn finalize_headers(&mut self) -> io::Result<()> {
         if self.request.header(HOST).is_none() {
             let url = self.request.url();
@@ -48,9 +118,13 @@ impl Encoder {
             self.request.insert_header("proxy-connection", "keep-alive");
         }
 
-        // If the body isn't streaming, we can set the content-length ahead of time. Else we need to
-        // send all items in chunks.
-        if let Some(len) = self.request.len() {
+        // Trailers can only follow a chunked body. Otherwise, if the body isn't streaming, we can
+        // set the content-length ahead of time. Else we need to send all items in chunks.
+        if self.request.has_trailers() {
+            self.trailers = Some(self.request.recv_trailers());
+            self.request.remove_header(CONTENT_LENGTH);
+            self.request.insert_header(TRANSFER_ENCODING, "chunked");
+        } else if let Some(len) = self.request.len() {
             self.request.insert_header(CONTENT_LENGTH, len.to_string());
         } else {
             self.request.insert_header(TRANSFER_ENCODING, "chunked");
@@ -105,6 +179,8 @@ impl Encoder {
 }
 
 impl Read for Encoder {
+    // `io::Error::other` would require Rust 1.74.
+    #[allow(unknown_lints, clippy::io_other_error)]
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
@@ -116,8 +192,7 @@ impl Read for Encoder {
 
                 EncoderState::Head(ref mut cursor) => {
                     read_to_end!(Pin::new(cursor).poll_read(cx, buf));
-                    let req_len = self.request.len();
-                    EncoderState::Body(BodyEncoder::new(self.request.take_body()), 0, req_len)
+                    self.body_state()
                 }
 
                 EncoderState::Body(ref mut encoder, ref mut n_written, req_len) => {
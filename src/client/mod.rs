@@ -1,25 +1,114 @@
 //! Process HTTP connections on the client.
 
-use async_std::io::{self, Read, Write};
+use async_std::io::{Read, Write};
 use http_types::{Request, Response};
 
+use crate::parse::HeadParser;
+use crate::{ParseMode, MAX_HEADERS, MAX_HEAD_LENGTH};
+
 mod decode;
 mod encode;
 
-pub use decode::decode;
+pub use decode::{decode, decode_with_opts};
 pub use encode::Encoder;
 
+/// Configure the client.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Maximum length of a response head in bytes. Defaults to 8KiB.
+    max_head_length: usize,
+    /// Maximum number of headers in a response head. Defaults to 128.
+    max_headers: usize,
+    /// How strictly response heads and trailers are parsed. Defaults to
+    /// lenient.
+    parse_mode: ParseMode,
+}
+
+impl ClientOptions {
+    /// Create a new instance with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum length of a response head in bytes.
+    ///
+    /// Responses with a longer head are rejected with
+    /// [`Error::HeadTooLarge`](crate::Error::HeadTooLarge).
+    pub fn with_max_head_length(mut self, max_head_length: usize) -> Self {
+        self.max_head_length = max_head_length;
+        self
+    }
+
+    /// Set the maximum number of headers in a response head.
+    ///
+    /// Responses with more headers are rejected with
+    /// [`Error::TooManyHeaders`](crate::Error::TooManyHeaders).
+    pub fn with_max_headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
+
+    /// Set how strictly response heads and the trailers of chunked response
+    /// bodies are parsed.
+    pub fn with_parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = parse_mode;
+        self
+    }
+
+    /// The maximum length of a response head in bytes.
+    pub fn max_head_length(&self) -> usize {
+        self.max_head_length
+    }
+
+    /// The maximum number of headers in a response head.
+    pub fn max_headers(&self) -> usize {
+        self.max_headers
+    }
+
+    /// How strictly response heads and trailers are parsed.
+    pub fn parse_mode(&self) -> ParseMode {
+        self.parse_mode
+    }
+
+    /// Create a parser for response heads with these limits.
+    pub(crate) fn head_parser(&self) -> HeadParser {
+        HeadParser::response(self.max_head_length, self.max_headers, self.parse_mode)
+    }
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            max_head_length: MAX_HEAD_LENGTH,
+            max_headers: MAX_HEADERS,
+            parse_mode: ParseMode::Lenient,
+        }
+    }
+}
+
 /// Opens an HTTP/1.1 connection to a remote host.
-pub async fn connect<RW>(mut stream: RW, req: Request) -> http_types::Result<Response>
+pub async fn connect<RW>(stream: RW, req: Request) -> http_types::Result<Response>
+where
+    RW: Read + Write + Send + Sync + Unpin + 'static,
+{
+    connect_with_opts(stream, req, ClientOptions::default()).await
+}
+
+/// Opens an HTTP/1.1 connection to a remote host, using the limits in `opts`.
+pub async fn connect_with_opts<RW>(
+    mut stream: RW,
+    req: Request,
+    opts: ClientOptions,
+) -> http_types::Result<Response>
 where
     RW: Read + Write + Send + Sync + Unpin + 'static,
 {
     let mut req = Encoder::new(req);
     log::trace!("> {:?}", &req);
 
-    io::copy(&mut req, &mut stream).await?;
+    req.write_to(&mut stream).await?;
 
-    let res = decode(stream).await?;
+    let res = decode_with_opts(stream, &opts).await?;
     log::trace!("< {:?}", &res);
 
     Ok(res)
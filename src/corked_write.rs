@@ -0,0 +1,150 @@
+//! Write an encoded message straight to a connection.
+
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::task::Poll;
+
+use async_std::future;
+use async_std::io::{self, Read, ReadExt, Write, WriteExt};
+
+/// The size of the buffer body bytes are read into.
+const BUF_SIZE: usize = 8 * 1024;
+
+/// Write `head`, then everything read from `body`, to `writer`, returning the
+/// number of bytes written.
+///
+/// The head is held back while the body's first bytes are ready right away,
+/// and is written together with them in one vectored write. A message with a
+/// small body thus goes out in a single write, while a body that keeps the
+/// client waiting doesn't hold back the head.
+pub(crate) async fn write_corked<B, W>(head: &[u8], body: &mut B, writer: &mut W) -> io::Result<u64>
+where
+    B: Read + Unpin + ?Sized,
+    W: Write + Unpin + ?Sized,
+{
+    let mut buf = vec![0; BUF_SIZE];
+    let mut head = head;
+    let mut written = 0;
+
+    let ready = future::poll_fn(|cx| Poll::Ready(Pin::new(&mut *body).poll_read(cx, &mut buf)));
+    let mut len = match ready.await {
+        Poll::Ready(len) => len?,
+        Poll::Pending => {
+            written += write_all_vectored(writer, &mut head, &[]).await?;
+            body.read(&mut buf).await?
+        }
+    };
+
+    loop {
+        written += write_all_vectored(writer, &mut head, &buf[..len]).await?;
+        if len == 0 {
+            break;
+        }
+        len = body.read(&mut buf).await?;
+    }
+
+    writer.flush().await?;
+    Ok(written)
+}
+
+/// Write what's left of `head` followed by `data`, with as few writes as
+/// `writer` allows.
+async fn write_all_vectored<W>(writer: &mut W, head: &mut &[u8], data: &[u8]) -> io::Result<u64>
+where
+    W: Write + Unpin + ?Sized,
+{
+    let mut data = data;
+    let mut written = 0;
+    while !head.is_empty() || !data.is_empty() {
+        let n = writer
+            .write_vectored(&[IoSlice::new(head), IoSlice::new(data)])
+            .await?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        let from_head = n.min(head.len());
+        *head = &head[from_head..];
+        data = &data[n - from_head..];
+        written += n as u64;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task::{self, Context};
+
+    /// A writer that records each write, accepting at most `limit` bytes at
+    /// a time.
+    struct RecordingWriter {
+        writes: Vec<Vec<u8>>,
+        limit: usize,
+    }
+
+    impl Write for RecordingWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let len = buf.len().min(self.limit);
+            self.writes.push(buf[..len].to_vec());
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_write_vectored(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            let mut write = bufs
+                .iter()
+                .flat_map(|buf| buf.iter().copied())
+                .collect::<Vec<_>>();
+            write.truncate(self.limit);
+            let len = write.len();
+            self.writes.push(write);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn write(head: &str, body: &str, limit: usize) -> Vec<Vec<u8>> {
+        task::block_on(async {
+            let mut writer = RecordingWriter {
+                writes: Vec::new(),
+                limit,
+            };
+            let mut body = io::Cursor::new(body);
+            let written = write_corked(head.as_bytes(), &mut body, &mut writer)
+                .await
+                .unwrap();
+            assert_eq!(written as usize, head.len() + body.get_ref().len());
+            writer.writes
+        })
+    }
+
+    #[test]
+    fn head_and_small_body_in_one_write() {
+        assert_eq!(
+            write("head\r\n\r\n", "body", 100),
+            vec![b"head\r\n\r\nbody"]
+        );
+        assert_eq!(write("head\r\n\r\n", "", 100), vec![b"head\r\n\r\n"]);
+    }
+
+    #[test]
+    fn partial_writes() {
+        let writes = write("head\r\n\r\n", "body", 3);
+        assert_eq!(writes.concat(), b"head\r\n\r\nbody");
+        assert!(writes.iter().all(|write| write.len() <= 3));
+    }
+}
@@ -1,7 +1,9 @@
+use std::cell::RefCell;
 use std::fmt::{self, Display, Formatter};
 use std::str::{from_utf8, FromStr};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use http_types::headers::HeaderValue;
 use http_types::{bail, ensure, format_err};
 
 const IMF_FIXDATE_LENGTH: usize = 29;
@@ -50,6 +52,53 @@ pub(crate) fn fmt_http_date(d: SystemTime) -> String {
     format!("{}", HttpDate::from(d))
 }
 
+/// The current date, as the value of a `Date` header field.
+///
+/// The formatted date is cached per thread, and only formatted again once
+/// the clock has moved on to the next second.
+pub(crate) fn http_date_now() -> HeaderValue {
+    thread_local! {
+        static CACHED_DATE: RefCell<CachedDate> = RefCell::new(CachedDate::new(UNIX_EPOCH));
+    }
+
+    let now = SystemTime::now();
+    CACHED_DATE.with(|cached| cached.borrow_mut().at(now).clone())
+}
+
+/// A date formatted for a header field, good for the whole second it
+/// falls in.
+#[derive(Debug)]
+struct CachedDate {
+    secs_since_epoch: u64,
+    value: HeaderValue,
+}
+
+impl CachedDate {
+    fn new(now: SystemTime) -> Self {
+        let value = HeaderValue::from_bytes(fmt_http_date(now).into_bytes())
+            .expect("formatted dates are valid header values");
+        Self {
+            secs_since_epoch: secs_since_epoch(now),
+            value,
+        }
+    }
+
+    /// The date at `now`, formatted again if it's in a different second.
+    fn at(&mut self, now: SystemTime) -> &HeaderValue {
+        if secs_since_epoch(now) != self.secs_since_epoch {
+            *self = Self::new(now);
+        }
+        &self.value
+    }
+}
+
+/// Clocks set before the epoch are treated as the epoch.
+fn secs_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 impl HttpDate {
     fn is_valid(self) -> bool {
         self.second < 60
@@ -210,10 +259,7 @@ fn parse_asctime(s: &[u8]) -> http_types::Result<HttpDate> {
 
 impl From<SystemTime> for HttpDate {
     fn from(system_time: SystemTime) -> Self {
-        let dur = system_time
-            .duration_since(UNIX_EPOCH)
-            .expect("all times should be after the epoch");
-        let secs_since_epoch = dur.as_secs();
+        let secs_since_epoch = secs_since_epoch(system_time);
 
         if secs_since_epoch >= YEAR_9999_SECONDS {
             // year 9999
@@ -375,8 +421,8 @@ impl Display for HttpDate {
         buf[0] = week_day[0];
         buf[1] = week_day[1];
         buf[2] = week_day[2];
-        buf[5] = b'0' + (self.day / 10) as u8;
-        buf[6] = b'0' + (self.day % 10) as u8;
+        buf[5] = b'0' + (self.day / 10);
+        buf[6] = b'0' + (self.day % 10);
         buf[8] = month[0];
         buf[9] = month[1];
         buf[10] = month[2];
@@ -384,12 +430,12 @@ impl Display for HttpDate {
         buf[13] = b'0' + (self.year / 100 % 10) as u8;
         buf[14] = b'0' + (self.year / 10 % 10) as u8;
         buf[15] = b'0' + (self.year % 10) as u8;
-        buf[17] = b'0' + (self.hour / 10) as u8;
-        buf[18] = b'0' + (self.hour % 10) as u8;
-        buf[20] = b'0' + (self.minute / 10) as u8;
-        buf[21] = b'0' + (self.minute % 10) as u8;
-        buf[23] = b'0' + (self.second / 10) as u8;
-        buf[24] = b'0' + (self.second % 10) as u8;
+        buf[17] = b'0' + (self.hour / 10);
+        buf[18] = b'0' + (self.hour % 10);
+        buf[20] = b'0' + (self.minute / 10);
+        buf[21] = b'0' + (self.minute % 10);
+        buf[23] = b'0' + (self.second / 10);
+        buf[24] = b'0' + (self.second % 10);
         f.write_str(from_utf8(&buf[..]).unwrap())
     }
 }
@@ -406,6 +452,8 @@ impl PartialOrd for HttpDate {
     }
 }
 
+// `u16::is_multiple_of` would require Rust 1.87.
+#[allow(unknown_lints, clippy::manual_is_multiple_of)]
 fn is_leap_year(year: u16) -> bool {
     year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
 }
@@ -414,7 +462,9 @@ fn is_leap_year(year: u16) -> bool {
 mod tests {
     use std::time::{Duration, UNIX_EPOCH};
 
-    use super::{fmt_http_date, parse_http_date, HttpDate, SECONDS_IN_DAY, SECONDS_IN_HOUR};
+    use super::{
+        fmt_http_date, parse_http_date, CachedDate, HttpDate, SECONDS_IN_DAY, SECONDS_IN_HOUR,
+    };
 
     #[test]
     fn test_rfc_example() {
@@ -472,6 +522,26 @@ mod tests {
         assert_eq!(fmt_http_date(d), "Sun, 02 Oct 2016 14:44:11 GMT");
     }
 
+    #[test]
+    fn fmt_before_epoch() {
+        let d = UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(fmt_http_date(d), "Thu, 01 Jan 1970 00:00:00 GMT");
+        let mut cached = CachedDate::new(d);
+        assert_eq!(cached.at(d).as_str(), "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn cached_date() {
+        let d = UNIX_EPOCH + Duration::from_secs(1475419451);
+        let mut cached = CachedDate::new(d);
+        let date = cached.at(d + Duration::from_millis(999));
+        assert_eq!(date.as_str(), "Sun, 02 Oct 2016 14:44:11 GMT");
+        let date = cached.at(d + Duration::from_secs(1));
+        assert_eq!(date.as_str(), "Sun, 02 Oct 2016 14:44:12 GMT");
+        let date = cached.at(d);
+        assert_eq!(date.as_str(), "Sun, 02 Oct 2016 14:44:11 GMT");
+    }
+
     #[test]
     fn size_of() {
         assert_eq!(::std::mem::size_of::<HttpDate>(), 8);
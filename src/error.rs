@@ -0,0 +1,119 @@
+//! Errors produced while processing HTTP messages.
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::time::Duration;
+
+use http_types::StatusCode;
+
+/// A protocol-level error encountered while decoding an HTTP message.
+///
+/// These errors are returned wrapped in an [`http_types::Error`] whose status
+/// is set to [`Error::status`], and can be recovered with
+/// [`http_types::Error::downcast_ref`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The head section was longer than the configured maximum, in bytes.
+    HeadTooLarge(usize),
+
+    /// The head section contained more headers than the configured maximum.
+    TooManyHeaders(usize),
+
+    /// The request line alone was longer than the configured maximum head
+    /// length, in bytes.
+    UriTooLong(usize),
+
+    /// The request body was longer than the configured maximum, in bytes.
+    BodyTooLarge(u64),
+
+    /// The request body made no progress for longer than the configured
+    /// body timeout.
+    BodyTimeout(Duration),
+
+    /// Writing the response made no progress for longer than the configured
+    /// write timeout.
+    WriteTimeout(Duration),
+}
+
+impl Error {
+    /// The status code a server should respond with when this error occurs.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::HeadTooLarge(_) => StatusCode::RequestHeaderFieldsTooLarge,
+            Error::TooManyHeaders(_) => StatusCode::RequestHeaderFieldsTooLarge,
+            Error::UriTooLong(_) => StatusCode::UriTooLong,
+            Error::BodyTooLarge(_) => StatusCode::PayloadTooLarge,
+            Error::BodyTimeout(_) => StatusCode::RequestTimeout,
+            Error::WriteTimeout(_) => StatusCode::InternalServerError,
+        }
+    }
+
+    /// Wrap this error in an `http_types::Error` carrying the matching status.
+    pub(crate) fn into_http(self) -> http_types::Error {
+        http_types::Error::new(self.status(), self)
+    }
+
+    /// Convert an I/O error into an `http_types::Error`, recovering the
+    /// original `Error` if the I/O error wraps one.
+    pub(crate) fn from_io(error: io::Error) -> http_types::Error {
+        match Self::find_in(&error) {
+            Some(error) => error.into_http(),
+            None => error.into(),
+        }
+    }
+
+    /// Give an endpoint error the status of the `Error` it wraps, such as a
+    /// failed body read returned with `?`.
+    pub(crate) fn recover_status(error: http_types::Error) -> http_types::Error {
+        match error.downcast_ref::<io::Error>().and_then(Self::find_in) {
+            Some(inner) => inner.into_http(),
+            None => error,
+        }
+    }
+
+    /// Look for an `Error` in the chain of I/O errors, which may have been
+    /// wrapped with additional context along the way.
+    fn find_in(error: &io::Error) -> Option<Error> {
+        let mut source = error
+            .get_ref()
+            .map(|e| e as &(dyn std::error::Error + 'static));
+        while let Some(error) = source {
+            if let Some(error) = error.downcast_ref::<Error>() {
+                return Some(*error);
+            }
+            source = match error.downcast_ref::<io::Error>() {
+                Some(error) => error
+                    .get_ref()
+                    .map(|e| e as &(dyn std::error::Error + 'static)),
+                None => error.source(),
+            };
+        }
+        None
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::HeadTooLarge(max) => {
+                write!(f, "Head byte length should be at most {} bytes", max)
+            }
+            Error::TooManyHeaders(max) => write!(f, "Head should contain at most {} headers", max),
+            Error::UriTooLong(max) => {
+                write!(f, "Request line should be at most {} bytes", max)
+            }
+            Error::BodyTooLarge(max) => {
+                write!(f, "Request body should be at most {} bytes", max)
+            }
+            Error::BodyTimeout(timeout) => {
+                write!(f, "Request body made no progress for {:?}", timeout)
+            }
+            Error::WriteTimeout(timeout) => {
+                write!(f, "Response write made no progress for {:?}", timeout)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -87,6 +87,9 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! [`server::serve`] wraps this loop, and adds a connection limit and
+//! graceful shutdown.
 
 #![forbid(unsafe_code)]
 #![deny(missing_debug_implementations, nonstandard_style, rust_2018_idioms)]
@@ -97,16 +100,20 @@
 #![allow(clippy::match_bool)]
 #![allow(clippy::unreadable_literal)]
 
-/// The maximum amount of headers parsed on the server.
+/// The default maximum amount of headers parsed in a head section.
 const MAX_HEADERS: usize = 128;
 
-/// The maximum length of the head section we'll try to parse.
+/// The default maximum length of the head section we'll try to parse.
 /// See: https://nodejs.org/en/blog/vulnerability/november-2018-security-releases/#denial-of-service-with-large-http-headers-cve-2018-12121
 const MAX_HEAD_LENGTH: usize = 8 * 1024;
 
 mod body_encoder;
 mod chunked;
+mod corked_write;
 mod date;
+mod error;
+mod parse;
+mod read_buffer;
 mod read_notifier;
 
 pub mod client;
@@ -114,14 +121,16 @@ pub mod server;
 
 use async_std::io::Cursor;
 use body_encoder::BodyEncoder;
-pub use client::connect;
+pub use client::{connect, connect_with_opts, ClientOptions};
+pub use error::Error;
+pub use parse::ParseMode;
 pub use server::{accept, accept_with_opts, ServerOptions};
 
 #[derive(Debug)]
 pub(crate) enum EncoderState {
     Start,
     Head(Cursor<Vec<u8>>),
-    Body(BodyEncoder, usize, Option<usize>),
+    Body(Box<BodyEncoder>, usize, Option<usize>),
     End,
 }
 
@@ -0,0 +1,411 @@
+//! Parsing profiles for message heads.
+
+use std::borrow::Cow;
+use std::mem;
+
+use http_types::{bail_status, format_err_status};
+use httparse::Status;
+
+use crate::{Error, MAX_HEADERS};
+
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+
+/// How strictly message heads and chunked trailers are parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Follow RFC 7230 to the letter.
+    ///
+    /// Lines must end with CRLF, and obsolete line folding, whitespace
+    /// between a field name and its colon, and extra whitespace in the start
+    /// line are rejected.
+    Strict,
+    /// Accept common deviations from RFC 7230.
+    ///
+    /// - Lines may end with a bare LF.
+    /// - Obsolete line folding is replaced with a single space.
+    /// - Whitespace between a field name and its colon is removed.
+    /// - Runs of spaces between the parts of the start line are accepted, and
+    ///   trailing whitespace is removed.
+    Lenient,
+}
+
+impl ParseMode {
+    /// Check a head httparse accepted, which is as lenient about bare LFs as
+    /// we are in lenient mode.
+    pub(crate) fn check_head(self, head: &[u8]) -> Result<(), &'static str> {
+        let bare_lf = |(i, &b): (usize, &u8)| b == LF && (i == 0 || head[i - 1] != CR);
+        match self {
+            ParseMode::Strict if head.iter().enumerate().any(bare_lf) => Err("Bare LF in head"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Normalize a head into the form httparse expects.
+    ///
+    /// In strict mode the head is passed through untouched, except that bare
+    /// LFs are rejected.
+    pub(crate) fn normalize(self, head: &[u8]) -> Result<Cow<'_, [u8]>, &'static str> {
+        self.normalize_lines(head, true)
+    }
+
+    /// Normalize a trailer section, which unlike a head has no start line.
+    pub(crate) fn normalize_trailers(self, trailers: &[u8]) -> Result<Cow<'_, [u8]>, &'static str> {
+        self.normalize_lines(trailers, false)
+    }
+
+    fn normalize_lines(self, head: &[u8], start_line: bool) -> Result<Cow<'_, [u8]>, &'static str> {
+        match self {
+            ParseMode::Strict => {
+                self.check_head(head)?;
+                Ok(Cow::Borrowed(head))
+            }
+            ParseMode::Lenient => Ok(Cow::Owned(normalize_lenient(head, start_line))),
+        }
+    }
+
+    /// Configure httparse for this mode.
+    pub(crate) fn parser_config(self) -> httparse::ParserConfig {
+        let mut config = httparse::ParserConfig::default();
+        if self == ParseMode::Lenient {
+            config
+                .allow_multiple_spaces_in_request_line_delimiters(true)
+                .allow_multiple_spaces_in_response_status_delimiters(true);
+        }
+        config
+    }
+}
+
+/// The outcome of running httparse over a head: its length and the message
+/// built from it, once it's complete.
+pub(crate) type Parsed<T> = Result<Status<(usize, http_types::Result<T>)>, httparse::Error>;
+
+/// Parses message heads out of a read buffer, with the checks requests and
+/// responses share: the maximum head length and number of headers, and the
+/// parse mode.
+#[derive(Debug)]
+pub(crate) struct HeadParser {
+    max_head_length: usize,
+    max_headers: usize,
+    parse_mode: ParseMode,
+    /// The error for a start line that alone exceeds the maximum head length.
+    start_line_too_long: Error,
+    /// Room for more headers than fit on the stack, kept across heads.
+    headers: Vec<httparse::Header<'static>>,
+}
+
+impl HeadParser {
+    /// Create a parser for request heads, whose start line holds the request
+    /// target.
+    pub(crate) fn request(
+        max_head_length: usize,
+        max_headers: usize,
+        parse_mode: ParseMode,
+    ) -> Self {
+        Self {
+            max_head_length,
+            max_headers,
+            parse_mode,
+            start_line_too_long: Error::UriTooLong(max_head_length),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Create a parser for response heads.
+    pub(crate) fn response(
+        max_head_length: usize,
+        max_headers: usize,
+        parse_mode: ParseMode,
+    ) -> Self {
+        Self {
+            max_head_length,
+            max_headers,
+            parse_mode,
+            start_line_too_long: Error::HeadTooLarge(max_head_length),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Parse a head out of the start of `buf`, which may also hold bytes past
+    /// the end of the head.
+    ///
+    /// `parse` runs httparse over a head with room for the headers, and builds
+    /// the message once the head is complete. In lenient mode, a head httparse
+    /// rejects is parsed again once it's whole and normalized.
+    ///
+    /// Returns `None` if `buf` doesn't hold the whole head yet, or else the
+    /// message and the length of its head.
+    pub(crate) fn parse<T, F>(
+        &mut self,
+        buf: &[u8],
+        mut parse: F,
+    ) -> http_types::Result<Option<(T, usize)>>
+    where
+        F: for<'b> FnMut(
+            &httparse::ParserConfig,
+            &'b [u8],
+            &mut [httparse::Header<'b>],
+        ) -> Parsed<T>,
+    {
+        let config = self.parse_mode.parser_config();
+        let len = match self.with_headers(|headers| parse(&config, buf, headers)) {
+            Ok(Status::Complete((len, message))) => {
+                self.check_len(buf, len)?;
+                self.parse_mode
+                    .check_head(&buf[..len])
+                    .map_err(|e| format_err_status!(400, "{}", e))?;
+                return Ok(Some((message?, len)));
+            }
+            Ok(Status::Partial) => {
+                self.check_len(buf, buf.len())?;
+                return Ok(None);
+            }
+            // Lenient heads may only parse once they're normalized, which
+            // needs the whole head.
+            Err(_) if self.parse_mode == ParseMode::Lenient => match head_len(buf) {
+                Some(len) => len,
+                None => {
+                    self.check_len(buf, buf.len())?;
+                    return Ok(None);
+                }
+            },
+            Err(e) => return Err(self.error(e)),
+        };
+
+        self.check_len(buf, len)?;
+        let head = self
+            .parse_mode
+            .normalize(&buf[..len])
+            .map_err(|e| format_err_status!(400, "{}", e))?;
+        let parsed = self.with_headers(|headers| parse(&config, &head, headers));
+        match parsed.map_err(|e| self.error(e))? {
+            Status::Complete((_, message)) => Ok(Some((message?, len))),
+            Status::Partial => bail_status!(400, "Malformed HTTP head"),
+        }
+    }
+
+    /// Run `f` with room for the maximum number of headers, on the stack if
+    /// they fit, or else in the allocation kept for them.
+    fn with_headers<'b, R>(&mut self, f: impl FnOnce(&mut [httparse::Header<'b>]) -> R) -> R {
+        if self.max_headers <= MAX_HEADERS {
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            return f(&mut headers[..self.max_headers]);
+        }
+        let mut headers = reuse(mem::take(&mut self.headers));
+        headers.resize(self.max_headers, httparse::EMPTY_HEADER);
+        let result = f(&mut headers);
+        self.headers = reuse(headers);
+        result
+    }
+
+    /// Check a head of `len` bytes at the start of `buf` against the maximum
+    /// head length.
+    fn check_len(&self, buf: &[u8], len: usize) -> http_types::Result<()> {
+        // Prevent CWE-400 DDOS with large HTTP Headers.
+        if len > self.max_head_length {
+            let err = match buf.iter().position(|&b| b == LF) {
+                Some(i) if i < self.max_head_length => Error::HeadTooLarge(self.max_head_length),
+                _ => self.start_line_too_long,
+            };
+            return Err(err.into_http());
+        }
+        Ok(())
+    }
+
+    fn error(&self, e: httparse::Error) -> http_types::Error {
+        match e {
+            httparse::Error::TooManyHeaders => Error::TooManyHeaders(self.max_headers).into_http(),
+            // httparse only knows HTTP/1.0 and HTTP/1.1.
+            httparse::Error::Version => format_err_status!(505, "Unsupported HTTP version"),
+            e => http_types::Error::new(400, e),
+        }
+    }
+}
+
+/// Empty `headers`, so that its allocation can hold headers borrowed from
+/// another buffer.
+fn reuse<'a, 'b>(mut headers: Vec<httparse::Header<'a>>) -> Vec<httparse::Header<'b>> {
+    headers.clear();
+    // Collecting into a vector of the same layout reuses the allocation.
+    headers
+        .into_iter()
+        .map(|_| httparse::EMPTY_HEADER)
+        .collect()
+}
+
+/// Find the length of the head at the start of `buf`, up to and including
+/// the empty line that ends it. Empty lines before the start line are part of
+/// the head.
+///
+/// Both CRLF and bare LF end lines, so this also finds heads that httparse
+/// rejects until they're normalized.
+pub(crate) fn head_len(buf: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    let mut start_line_read = false;
+    for (i, _) in buf.iter().enumerate().filter(|(_, &b)| b == LF) {
+        let line = &buf[start..i];
+        let empty = line.is_empty() || line == [CR];
+        if empty && start_line_read {
+            return Some(i + 1);
+        }
+        start_line_read |= !empty;
+        start = i + 1;
+    }
+    None
+}
+
+/// Rewrite a head leniently: end every line with CRLF, unfold folded lines,
+/// and strip whitespace that httparse would otherwise reject.
+fn normalize_lenient(head: &[u8], start_line: bool) -> Vec<u8> {
+    let mut normalized: Vec<u8> = Vec::with_capacity(head.len());
+    let mut lines = head
+        .split_inclusive(|&b| b == LF)
+        .map(|line| line.strip_suffix(&[LF]).unwrap_or(line))
+        .map(|line| line.strip_suffix(&[CR]).unwrap_or(line));
+
+    // Leading empty lines are kept, the start line loses trailing whitespace.
+    if start_line {
+        for line in lines.by_ref() {
+            normalized.extend_from_slice(trim_end(line));
+            normalized.extend_from_slice(b"\r\n");
+            if !line.is_empty() {
+                break;
+            }
+        }
+    }
+
+    let mut in_fields = true;
+    let mut seen_field = false;
+    for line in lines {
+        if !in_fields {
+            normalized.extend_from_slice(line);
+        } else if line.is_empty() {
+            in_fields = false;
+        } else if is_whitespace(line[0]) && seen_field {
+            // Replace the fold with a single space.
+            normalized.truncate(normalized.len() - 2);
+            normalized.push(b' ');
+            normalized.extend_from_slice(trim_start(line));
+        } else {
+            let line = trim_start(line);
+            match line.iter().position(|&b| b == b':') {
+                Some(colon) => {
+                    normalized.extend_from_slice(trim_end(&line[..colon]));
+                    normalized.extend_from_slice(&line[colon..]);
+                }
+                None => normalized.extend_from_slice(line),
+            }
+            seen_field = true;
+        }
+        normalized.extend_from_slice(b"\r\n");
+    }
+
+    // Keep a missing final line ending missing, so incomplete heads stay
+    // incomplete.
+    if !head.ends_with(&[LF]) {
+        normalized.truncate(normalized.len().saturating_sub(2));
+    }
+    normalized
+}
+
+fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+fn trim_start(mut bytes: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = bytes {
+        if !is_whitespace(*first) {
+            break;
+        }
+        bytes = rest;
+    }
+    bytes
+}
+
+fn trim_end(mut bytes: &[u8]) -> &[u8] {
+    while let [rest @ .., last] = bytes {
+        if !is_whitespace(*last) {
+            break;
+        }
+        bytes = rest;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lenient(head: &str) -> String {
+        let normalized = ParseMode::Lenient.normalize(head.as_bytes()).unwrap();
+        String::from_utf8(normalized.into_owned()).unwrap()
+    }
+
+    #[test]
+    fn lenient_normalization() {
+        assert_eq!(
+            lenient("GET / HTTP/1.1 \nHost: example.com\n\n"),
+            "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"
+        );
+        assert_eq!(
+            lenient("GET / HTTP/1.1\r\nX-Folded: a\r\n \tb\r\nHost : example.com\r\n\r\n"),
+            "GET / HTTP/1.1\r\nX-Folded: a b\r\nHost: example.com\r\n\r\n"
+        );
+        assert_eq!(lenient("GET / HTTP/1.1\r\nHost"), "GET / HTTP/1.1\r\nHost");
+    }
+
+    #[test]
+    fn finds_head_len() {
+        assert_eq!(head_len(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nbody"), Some(27));
+        assert_eq!(head_len(b"\r\nGET / HTTP/1.1\n\nbody"), Some(18));
+        assert_eq!(head_len(b"GET / HTTP/1.1\r\nHost: a\r\n"), None);
+        assert_eq!(head_len(b"\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn reuses_header_allocation() {
+        let mut headers = vec![httparse::EMPTY_HEADER; MAX_HEADERS * 2];
+        let buf = b"x-a".to_vec();
+        headers[0] = httparse::Header {
+            name: "x-a",
+            value: &buf,
+        };
+        let ptr = headers.as_ptr() as usize;
+        let reused = reuse(headers);
+        assert!(reused.is_empty());
+        assert_eq!(reused.as_ptr() as usize, ptr);
+        assert_eq!(reused.capacity(), MAX_HEADERS * 2);
+    }
+
+    #[test]
+    fn parses_more_headers_than_fit_on_the_stack() {
+        let mut head = b"GET / HTTP/1.1\r\n".to_vec();
+        for i in 0..MAX_HEADERS + 1 {
+            head.extend_from_slice(format!("x-{}: {}\r\n", i, i).as_bytes());
+        }
+        head.extend_from_slice(b"\r\n");
+
+        let mut parser = HeadParser::request(head.len(), MAX_HEADERS + 1, ParseMode::Strict);
+        for _ in 0..2 {
+            let parsed = parser.parse(&head, |config, buf, headers| {
+                let mut req = httparse::Request::new(headers);
+                Ok(match config.parse_request(&mut req, buf)? {
+                    Status::Complete(len) => Status::Complete((len, Ok(req.headers.len()))),
+                    Status::Partial => Status::Partial,
+                })
+            });
+            assert_eq!(parsed.unwrap(), Some((MAX_HEADERS + 1, head.len())));
+            assert_eq!(parser.headers.capacity(), MAX_HEADERS + 1);
+        }
+    }
+
+    #[test]
+    fn strict_rejects_bare_lf() {
+        assert!(ParseMode::Strict
+            .normalize(b"GET / HTTP/1.1\nHost: example.com\r\n\r\n")
+            .is_err());
+        assert!(ParseMode::Strict
+            .normalize(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .is_ok());
+    }
+}
@@ -0,0 +1,205 @@
+//! A read buffer that message heads are parsed straight out of.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_std::io::{BufRead, Read};
+use futures_core::ready;
+
+/// The initial capacity of the buffer.
+const CAPACITY: usize = 8 * 1024;
+
+/// A buffered reader whose buffer grows to hold a whole head, so the head can
+/// be parsed where it was read into. The buffer doubles in size whenever a
+/// head doesn't fit; how long a head may be is up to the caller of
+/// `poll_head`.
+///
+/// Whatever is left in the buffer after the head is served to the body. The
+/// buffer is only allocated once something is read.
+#[derive(Debug)]
+pub(crate) struct ReadBuffer<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+}
+
+impl<R: Read + Unpin> ReadBuffer<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    /// The bytes read but not yet consumed.
+    pub(crate) fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.filled]
+    }
+
+    /// Read more bytes onto the end of the buffer, doubling its size if it's
+    /// full.
+    ///
+    /// Returns the number of bytes read, which is 0 at the end of the stream.
+    pub(crate) fn poll_fill_more(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        if self.filled == self.buf.len() {
+            if self.pos > 0 {
+                self.buf.copy_within(self.pos..self.filled, 0);
+                self.filled -= self.pos;
+                self.pos = 0;
+            } else {
+                let len = std::cmp::max(self.buf.len() * 2, CAPACITY);
+                self.buf.resize(len, 0);
+            }
+        }
+        let read = ready!(Pin::new(&mut self.inner).poll_read(cx, &mut self.buf[self.filled..]))?;
+        self.filled += read;
+        Poll::Ready(Ok(read))
+    }
+
+    /// Read until `parse` finds a whole head at the start of the buffer, and
+    /// consume it.
+    ///
+    /// `parse` returns `None` while the head is incomplete, and is expected to
+    /// fail once the buffer is longer than any head it accepts. Reading stops
+    /// with an error once `limit` bytes are buffered without a whole head.
+    /// `parsed` tells whether `parse` already saw the buffer as it is, so it's
+    /// only run again once more bytes arrive. Returns `None` at the end of the
+    /// stream.
+    pub(crate) fn poll_head<T, E, F>(
+        &mut self,
+        cx: &mut Context<'_>,
+        limit: usize,
+        parsed: &mut bool,
+        parse: &mut F,
+    ) -> Poll<Result<Option<T>, E>>
+    where
+        F: FnMut(&[u8]) -> Result<Option<(T, usize)>, E>,
+        E: From<io::Error>,
+    {
+        loop {
+            if !*parsed && self.pos < self.filled {
+                *parsed = true;
+                if let Some((head, len)) = parse(self.buffer())? {
+                    Pin::new(&mut *self).consume(len);
+                    return Poll::Ready(Ok(Some(head)));
+                }
+            }
+            if self.buffer().len() >= limit {
+                let err = io::Error::new(io::ErrorKind::InvalidData, "head too long");
+                return Poll::Ready(Err(err.into()));
+            }
+            if ready!(self.poll_fill_more(cx))? == 0 {
+                return Poll::Ready(Ok(None));
+            }
+            *parsed = false;
+        }
+    }
+}
+
+impl<R: Read + Unpin> Read for ReadBuffer<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.pos == this.filled {
+            // Skip the copy through our buffer for large reads.
+            if buf.len() >= CAPACITY {
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            }
+        }
+        let available = ready!(Pin::new(&mut *this).poll_fill_buf(cx))?;
+        let len = std::cmp::min(available.len(), buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        Pin::new(this).consume(len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl<R: Read + Unpin> BufRead for ReadBuffer<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.pos == this.filled {
+            ready!(this.poll_fill_more(cx))?;
+        }
+        Poll::Ready(Ok(this.buffer()))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.pos = std::cmp::min(this.pos + amt, this.filled);
+        if this.pos == this.filled {
+            this.pos = 0;
+            this.filled = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::future;
+    use async_std::io::Cursor;
+    use async_std::task;
+
+    /// Parse a head that ends at the first LF.
+    fn parse_line(buf: &[u8]) -> io::Result<Option<((), usize)>> {
+        Ok(buf.iter().position(|&b| b == b'\n').map(|i| ((), i + 1)))
+    }
+
+    #[test]
+    fn head_limit_does_not_cap_body_reads() {
+        task::block_on(async {
+            let mut input = b"HEAD\n".to_vec();
+            input.resize(input.len() + CAPACITY * 2, b'x');
+            let mut reader = ReadBuffer::new(Cursor::new(input));
+
+            let mut parsed = false;
+            let mut parse = parse_line;
+            let head = future::poll_fn(|cx| reader.poll_head(cx, 16, &mut parsed, &mut parse));
+            assert_eq!(head.await.unwrap(), Some(()));
+
+            let body = future::poll_fn(|cx| {
+                Pin::new(&mut reader)
+                    .poll_fill_buf(cx)
+                    .map_ok(|buf| buf.len())
+            });
+            assert!(body.await.unwrap() > 16);
+        });
+    }
+
+    #[test]
+    fn grows_to_fit_a_head() {
+        task::block_on(async {
+            let mut input = vec![b'x'; CAPACITY * 3];
+            input.extend_from_slice(b"\nbody");
+            let mut reader = ReadBuffer::new(Cursor::new(input));
+
+            let mut parsed = false;
+            let mut parse = parse_line;
+            let limit = CAPACITY * 4;
+            let head = future::poll_fn(|cx| reader.poll_head(cx, limit, &mut parsed, &mut parse));
+            assert_eq!(head.await.unwrap(), Some(()));
+            assert_eq!(reader.buf.len(), CAPACITY * 4);
+            assert_eq!(reader.buffer(), b"body");
+        });
+    }
+
+    #[test]
+    fn head_over_the_limit() {
+        task::block_on(async {
+            let mut reader = ReadBuffer::new(Cursor::new(vec![b'x'; CAPACITY * 3]));
+
+            let mut parsed = false;
+            let mut parse = parse_line;
+            let head = future::poll_fn(|cx| reader.poll_head(cx, 100, &mut parsed, &mut parse));
+            let err = head.await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        });
+    }
+}
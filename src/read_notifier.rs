@@ -2,43 +2,75 @@ use std::fmt;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use async_channel::Sender;
 use async_std::io::{self, BufRead, Read};
+use futures_core::ready;
+
+use crate::server::ExpectContinue;
 
 /// ReadNotifier forwards [`async_std::io::Read`] and
 /// [`async_std::io::BufRead`] to an inner reader. When the
 /// ReadNotifier is read from (using `Read`, `ReadExt`, or `BufRead`
-/// methods), it sends a single message containing `()` on the
-/// channel.
+/// methods), it first writes the `100 Continue` the request expects, if
+/// it's been requested. With `auto_continue`, reading requests it;
+/// otherwise reading waits until it's requested or the response starts.
 #[pin_project::pin_project]
 pub(crate) struct ReadNotifier<B> {
     #[pin]
     reader: B,
-    sender: Sender<()>,
-    has_been_read: bool,
+    expect: Option<ExpectContinue>,
+    auto_continue: bool,
 }
 
 impl<B> fmt::Debug for ReadNotifier<B> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ReadNotifier")
-            .field("read", &self.has_been_read)
+            .field("expect", &self.expect)
+            .field("auto_continue", &self.auto_continue)
             .finish()
     }
 }
 
 impl<B: Read> ReadNotifier<B> {
-    pub(crate) fn new(reader: B, sender: Sender<()>) -> Self {
+    pub(crate) fn new(reader: B, expect: Option<ExpectContinue>, auto_continue: bool) -> Self {
         Self {
             reader,
-            sender,
-            has_been_read: false,
+            expect,
+            auto_continue,
         }
     }
+
+    /// Get a reference to the inner reader.
+    pub(crate) fn get_ref(&self) -> &B {
+        &self.reader
+    }
+
+    /// Unwrap the inner reader, without writing any `100 Continue`.
+    pub(crate) fn into_inner(self) -> B {
+        self.reader
+    }
+}
+
+/// Write `100 Continue` ahead of reading, if it's expected.
+fn poll_continue(
+    expect: &mut Option<ExpectContinue>,
+    auto_continue: bool,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<()>> {
+    if let Some(continue_) = expect {
+        if auto_continue {
+            continue_.send();
+        }
+        ready!(continue_.poll_write(cx))?;
+        *expect = None;
+    }
+    Poll::Ready(Ok(()))
 }
 
 impl<B: BufRead> BufRead for ReadNotifier<B> {
     fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
-        self.project().reader.poll_fill_buf(cx)
+        let this = self.project();
+        ready!(poll_continue(this.expect, *this.auto_continue, cx))?;
+        this.reader.poll_fill_buf(cx)
     }
 
     fn consume(self: Pin<&mut Self>, amt: usize) {
@@ -53,13 +85,7 @@ impl<B: Read> Read for ReadNotifier<B> {
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
         let this = self.project();
-
-        if !*this.has_been_read {
-            if let Ok(()) = this.sender.try_send(()) {
-                *this.has_been_read = true;
-            };
-        }
-
+        ready!(poll_continue(this.expect, *this.auto_continue, cx))?;
         this.reader.poll_read(cx, buf)
     }
 }
@@ -1,12 +1,209 @@
+use super::idle_timeout::IdleTimeout;
 use crate::chunked::ChunkedDecoder;
+use crate::read_buffer::ReadBuffer;
+use crate::read_notifier::ReadNotifier;
 use async_dup::{Arc, Mutex};
-use async_std::io::{BufReader, Read, Take};
+use async_std::io::{BufRead, Read, Take};
 use async_std::task::{Context, Poll};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::{fmt::Debug, io, pin::Pin};
 
+/// How a request body is framed on the connection.
+#[derive(Debug)]
+pub(crate) enum Framing<IO: Read + Unpin> {
+    Chunked(ChunkedDecoder<ReadBuffer<IO>>),
+    Fixed(Take<ReadBuffer<IO>>),
+}
+
+impl<IO: Read + Unpin> Framing<IO> {
+    /// The number of body bytes left to read, if known ahead of time.
+    fn remaining(&self) -> Option<u64> {
+        match self {
+            Framing::Chunked(_) => None,
+            Framing::Fixed(r) => Some(r.limit()),
+        }
+    }
+
+    /// Whether the body was found to exceed the maximum body length while
+    /// reading it.
+    pub(crate) fn is_too_large(&self) -> bool {
+        match self {
+            Framing::Chunked(r) => r.is_too_large(),
+            Framing::Fixed(_) => false,
+        }
+    }
+
+    /// Unwrap the connection's read buffer, with whatever was read past the
+    /// end of the body.
+    pub(crate) fn into_reader(self) -> ReadBuffer<IO> {
+        match self {
+            Framing::Chunked(r) => r.into_inner(),
+            Framing::Fixed(r) => r.into_inner(),
+        }
+    }
+}
+
+impl<IO: Read + Unpin> Read for Framing<IO> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Framing::Chunked(r) => Pin::new(r).poll_read(cx, buf),
+            Framing::Fixed(r) => Pin::new(r).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<IO: Read + Unpin> BufRead for Framing<IO> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        match self.get_mut() {
+            Framing::Chunked(r) => Pin::new(r).poll_fill_buf(cx),
+            Framing::Fixed(r) => Pin::new(r).poll_fill_buf(cx),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        match self.get_mut() {
+            Framing::Chunked(r) => Pin::new(r).consume(amt),
+            Framing::Fixed(r) => Pin::new(r).consume(amt),
+        }
+    }
+}
+
+/// The reader of a request body, from the connection's read buffer up.
+type Reader<IO> = IdleTimeout<Framing<IO>>;
+
+/// What the server knows about a request body while the endpoint reads it.
+pub struct Shared<IO: Read + Unpin> {
+    /// The reader, once the request body hands it back.
+    reader: Mutex<Option<Reader<IO>>>,
+    remaining: AtomicU64,
+    too_large: AtomicBool,
+    timed_out: AtomicBool,
+}
+
+impl<IO: Read + Unpin> Debug for Shared<IO> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shared")
+            .field("remaining", &self.remaining)
+            .field("too_large", &self.too_large)
+            .field("timed_out", &self.timed_out)
+            .finish()
+    }
+}
+
+/// The body of a request, as handed to the endpoint.
+///
+/// It reads straight out of the connection's read buffer, and hands the
+/// reader back to the server once it's dropped.
+pub(crate) struct RequestBody<IO: Read + Unpin> {
+    reader: Option<ReadNotifier<Reader<IO>>>,
+    shared: Arc<Shared<IO>>,
+}
+
+impl<IO: Read + Unpin> RequestBody<IO> {
+    /// Create the body of a request, along with the server's handle on it.
+    pub(crate) fn new(reader: ReadNotifier<Reader<IO>>) -> (Self, BodyReader<IO>) {
+        let framing = reader.get_ref().get_ref();
+        let shared = Arc::new(Shared {
+            reader: Mutex::new(None),
+            remaining: AtomicU64::new(framing.remaining().unwrap_or(0)),
+            too_large: AtomicBool::new(false),
+            timed_out: AtomicBool::new(false),
+        });
+        let body_reader = match framing {
+            Framing::Chunked(_) => BodyReader::Chunked(shared.clone()),
+            Framing::Fixed(_) => BodyReader::Fixed(shared.clone()),
+        };
+        let body = Self {
+            reader: Some(reader),
+            shared,
+        };
+        (body, body_reader)
+    }
+
+    fn reader(&mut self) -> Pin<&mut ReadNotifier<Reader<IO>>> {
+        Pin::new(self.reader.as_mut().expect("request body already dropped"))
+    }
+
+    /// Let the server know how far the body has been read.
+    fn update_shared(&self) {
+        if let Some(reader) = &self.reader {
+            let reader = reader.get_ref();
+            let framing = reader.get_ref();
+            let remaining = framing.remaining().unwrap_or(0);
+            self.shared.remaining.store(remaining, Ordering::Relaxed);
+            self.shared
+                .too_large
+                .store(framing.is_too_large(), Ordering::Relaxed);
+            self.shared
+                .timed_out
+                .store(reader.timed_out(), Ordering::Relaxed);
+        }
+    }
+}
+
+impl<IO: Read + Unpin> Debug for RequestBody<IO> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestBody")
+            .field("reader", &self.reader)
+            .finish()
+    }
+}
+
+impl<IO: Read + Unpin> Drop for RequestBody<IO> {
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            *self.shared.reader.lock() = Some(reader.into_inner());
+        }
+    }
+}
+
+impl<IO: Read + Unpin> Read for RequestBody<IO> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = this.reader().poll_read(cx, buf);
+        this.update_shared();
+        poll
+    }
+}
+
+impl<IO: Read + Unpin> BufRead for RequestBody<IO> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        // Errors are what change the state the server looks at, so they're
+        // handled before borrowing the buffer, which is then ready at once.
+        match this.reader().poll_fill_buf(cx) {
+            Poll::Ready(Ok(_)) => {}
+            Poll::Ready(Err(e)) => {
+                this.update_shared();
+                return Poll::Ready(Err(e));
+            }
+            Poll::Pending => {
+                this.update_shared();
+                return Poll::Pending;
+            }
+        }
+        this.reader().poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.reader().consume(amt);
+        this.update_shared();
+    }
+}
+
+/// The server's handle on the body of a request.
 pub enum BodyReader<IO: Read + Unpin> {
-    Chunked(Arc<Mutex<ChunkedDecoder<BufReader<IO>>>>),
-    Fixed(Arc<Mutex<Take<BufReader<IO>>>>),
+    Chunked(Arc<Shared<IO>>),
+    Fixed(Arc<Shared<IO>>),
     None,
 }
 
@@ -20,16 +217,46 @@ impl<IO: Read + Unpin> Debug for BodyReader<IO> {
     }
 }
 
-impl<IO: Read + Unpin> Read for BodyReader<IO> {
-    fn poll_read(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut [u8],
-    ) -> Poll<io::Result<usize>> {
-        match &*self {
-            BodyReader::Chunked(r) => Pin::new(&mut *r.lock()).poll_read(cx, buf),
-            BodyReader::Fixed(r) => Pin::new(&mut *r.lock()).poll_read(cx, buf),
-            BodyReader::None => Poll::Ready(Ok(0)),
+impl<IO: Read + Unpin> BodyReader<IO> {
+    fn shared(&self) -> Option<&Shared<IO>> {
+        match self {
+            BodyReader::Chunked(shared) | BodyReader::Fixed(shared) => Some(shared),
+            BodyReader::None => None,
+        }
+    }
+
+    /// The number of body bytes left to read, if known ahead of time.
+    pub(crate) fn remaining(&self) -> Option<u64> {
+        match self {
+            BodyReader::Chunked(_) => None,
+            BodyReader::Fixed(shared) => Some(shared.remaining.load(Ordering::Relaxed)),
+            BodyReader::None => Some(0),
+        }
+    }
+
+    /// Whether the body was found to exceed the maximum body length while
+    /// reading it.
+    pub(crate) fn is_too_large(&self) -> bool {
+        self.shared()
+            .map(|shared| shared.too_large.load(Ordering::Relaxed))
+            .unwrap_or(false)
+    }
+
+    /// Whether the body stopped making progress for longer than the body
+    /// timeout.
+    pub(crate) fn timed_out(&self) -> bool {
+        self.shared()
+            .map(|shared| shared.timed_out.load(Ordering::Relaxed))
+            .unwrap_or(false)
+    }
+
+    /// Take back the body's reader, once the request body has been dropped.
+    ///
+    /// Returns `Err` if there is a body and it's still held on to.
+    pub(crate) fn take_reader(&self) -> Result<Option<Reader<IO>>, ()> {
+        match self.shared() {
+            Some(shared) => shared.reader.lock().take().map(Some).ok_or(()),
+            None => Ok(None),
         }
     }
 }
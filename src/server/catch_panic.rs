@@ -0,0 +1,30 @@
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+use async_std::future;
+use async_std::task::Poll;
+
+/// Run `fut` to completion, catching any panic raised while polling it.
+pub(crate) async fn catch_panic<F: Future>(fut: F) -> thread::Result<F::Output> {
+    let mut fut = Box::pin(fut);
+    future::poll_fn(
+        |cx| match panic::catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => Poll::Ready(Err(payload)),
+        },
+    )
+    .await
+}
+
+/// Get the message of a panic payload, if it has one.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        Some(message)
+    } else {
+        payload
+            .downcast_ref::<String>()
+            .map(|message| message.as_str())
+    }
+}
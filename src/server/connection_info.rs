@@ -0,0 +1,103 @@
+use std::fmt::Display;
+
+/// Information about the connection requests are received on.
+///
+/// Set it with [`ServerOptions::with_connection_info`] for each connection.
+/// Decoded requests carry their peer and local addresses, use the `https`
+/// scheme over TLS, and have a copy of this in their extensions.
+///
+/// [`ServerOptions::with_connection_info`]: super::ServerOptions::with_connection_info
+///
+/// # Example
+///
+/// ```
+/// use async_h1::server::ConnectionInfo;
+/// use async_h1::ServerOptions;
+///
+/// let info = ConnectionInfo::new()
+///     .with_peer_addr("203.0.113.7:51234")
+///     .with_local_addr("192.0.2.1:443")
+///     .with_tls(true)
+///     .with_alpn_protocol("http/1.1")
+///     .with_server_name("example.com");
+/// let opts = ServerOptions::new().with_connection_info(info);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    peer_addr: Option<String>,
+    local_addr: Option<String>,
+    tls: bool,
+    alpn_protocol: Option<String>,
+    server_name: Option<String>,
+}
+
+impl ConnectionInfo {
+    /// Create a new instance without any information.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the address of the remote end of the connection.
+    pub fn with_peer_addr(mut self, peer_addr: impl Display) -> Self {
+        self.peer_addr = Some(peer_addr.to_string());
+        self
+    }
+
+    /// Set the address of the local end of the connection.
+    pub fn with_local_addr(mut self, local_addr: impl Display) -> Self {
+        self.local_addr = Some(local_addr.to_string());
+        self
+    }
+
+    /// Set whether the connection is secured with TLS.
+    pub fn with_tls(mut self, tls: bool) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Set the protocol negotiated with ALPN.
+    pub fn with_alpn_protocol(mut self, alpn_protocol: impl Into<String>) -> Self {
+        self.alpn_protocol = Some(alpn_protocol.into());
+        self
+    }
+
+    /// Set the server name the client asked for with SNI.
+    pub fn with_server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    /// The address of the remote end of the connection.
+    pub fn peer_addr(&self) -> Option<&str> {
+        self.peer_addr.as_deref()
+    }
+
+    /// The address of the local end of the connection.
+    pub fn local_addr(&self) -> Option<&str> {
+        self.local_addr.as_deref()
+    }
+
+    /// Whether the connection is secured with TLS.
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// The protocol negotiated with ALPN.
+    pub fn alpn_protocol(&self) -> Option<&str> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The server name the client asked for with SNI.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// The URL scheme of requests received on the connection.
+    pub(crate) fn scheme(&self) -> &'static str {
+        if self.tls {
+            "https"
+        } else {
+            "http"
+        }
+    }
+}
@@ -1,166 +1,485 @@
 //! Process HTTP connections on the server.
 
+use std::mem;
+use std::pin::Pin;
 use std::str::FromStr;
-
-use async_dup::{Arc, Mutex};
-use async_std::io::{BufReader, Read, Write};
-use async_std::{prelude::*, task};
-use http_types::content::ContentLength;
-use http_types::headers::{EXPECT, TRANSFER_ENCODING};
-use http_types::{ensure, ensure_eq, format_err};
-use http_types::{Body, Method, Request, Url};
-
-use super::body_reader::BodyReader;
+use std::time::Duration;
+
+use async_std::future::{self, timeout, TimeoutError};
+use async_std::io::{BufRead, Read, Write};
+use async_std::prelude::*;
+use http_types::headers::{HeaderValues, CONTENT_LENGTH, EXPECT, TRANSFER_ENCODING};
+use http_types::{bail_status, ensure_status, format_err_status};
+use http_types::{Body, Method, Request, Status, Url, Version};
+
+use super::body_reader::{BodyReader, Framing, RequestBody};
+use super::expect::ExpectContinue;
+use super::idle_timeout::IdleTimeout;
+use super::target::RequestTarget;
+use super::ServerOptions;
 use crate::chunked::ChunkedDecoder;
+use crate::parse::HeadParser;
+use crate::read_buffer::ReadBuffer;
 use crate::read_notifier::ReadNotifier;
-use crate::{MAX_HEADERS, MAX_HEAD_LENGTH};
+use crate::Error;
 
-const LF: u8 = b'\n';
+/// The number returned from httparse when the request is HTTP 1.0
+const HTTP_1_0_VERSION: u8 = 0;
 
 /// The number returned from httparse when the request is HTTP 1.1
 const HTTP_1_1_VERSION: u8 = 1;
 
+/// The transfer codings we know of. Only chunked is decoded, the others are
+/// left for the endpoint to handle.
+const TRANSFER_CODINGS: &[&str] = &[
+    "chunked",
+    "compress",
+    "deflate",
+    "gzip",
+    "x-compress",
+    "x-gzip",
+];
+
 const CONTINUE_HEADER_VALUE: &str = "100-continue";
-const CONTINUE_RESPONSE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
 
 /// Decode an HTTP request on the server.
-pub async fn decode<IO>(mut io: IO) -> http_types::Result<Option<(Request, BodyReader<IO>)>>
+///
+/// This uses the default [`ServerOptions`], so it gives up on a head that
+/// isn't received within 60 seconds and returns `Ok(None)`.
+pub async fn decode<IO>(io: IO) -> http_types::Result<Option<(Request, BodyReader<IO>)>>
 where
     IO: Read + Write + Clone + Send + Sync + Unpin + 'static,
 {
-    let mut reader = BufReader::new(io.clone());
-    let mut buf = Vec::new();
-    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
-    let mut httparse_req = httparse::Request::new(&mut headers);
-
-    // Keep reading bytes from the stream until we hit the end of the stream.
-    loop {
-        let bytes_read = reader.read_until(LF, &mut buf).await?;
-        // No more bytes are yielded from the stream.
-        if bytes_read == 0 {
-            return Ok(None);
-        }
+    decode_with_opts(io, &ServerOptions::default()).await
+}
 
-        // Prevent CWE-400 DDOS with large HTTP Headers.
-        ensure!(
-            buf.len() < MAX_HEAD_LENGTH,
-            "Head byte length should be less than 8kb"
-        );
+/// Decode an HTTP request on the server, using the limits in `opts`.
+///
+/// Returns `Ok(None)` if the connection is closed or the head isn't received
+/// within the headers timeout, which defaults to 60 seconds. Use
+/// [`ServerOptions::with_headers_timeout`] with `None` to wait forever.
+pub async fn decode_with_opts<IO>(
+    io: IO,
+    opts: &ServerOptions,
+) -> http_types::Result<Option<(Request, BodyReader<IO>)>>
+where
+    IO: Read + Write + Clone + Send + Sync + Unpin + 'static,
+{
+    let mut reader = ReadBuffer::new(io.clone());
+    let mut parser = opts.head_parser();
+    decode_with_idle_timeout(io, &mut reader, &mut parser, opts, None, &mut None).await
+}
 
-        // We've hit the end delimiter of the stream.
-        let idx = buf.len() - 1;
-        if idx >= 3 && &buf[idx - 3..=idx] == b"\r\n\r\n" {
-            break;
+/// Decode an HTTP request on the server, first waiting at most `idle_timeout`
+/// for the client to start sending it.
+///
+/// The headers timeout only starts once the first byte has been received.
+/// The head is parsed with the connection's `parser`, and the request is read
+/// through its read buffer `reader`. If the
+/// request has a body, the buffer is moved into the request's body, which
+/// hands it back to the returned [`BodyReader`] once it's dropped.
+///
+/// `version` is set to the request's HTTP version as soon as it's parsed, so
+/// a request that fails to decode can still be answered in its version.
+pub(crate) async fn decode_with_idle_timeout<IO>(
+    io: IO,
+    reader: &mut ReadBuffer<IO>,
+    parser: &mut HeadParser,
+    opts: &ServerOptions,
+    idle_timeout: Option<Duration>,
+    version: &mut Option<Version>,
+) -> http_types::Result<Option<(Request, BodyReader<IO>)>>
+where
+    IO: Read + Write + Clone + Send + Sync + Unpin + 'static,
+{
+    if let Some(idle_timeout) = idle_timeout {
+        let fill_buf = future::poll_fn(|cx| {
+            Pin::new(&mut *reader)
+                .poll_fill_buf(cx)
+                .map_ok(|buf| buf.is_empty())
+        });
+        match timeout(idle_timeout, fill_buf).await {
+            Ok(Ok(false)) => {}
+            Ok(Ok(true)) | Err(TimeoutError { .. }) => return Ok(None), /* EOF or timeout */
+            Ok(Err(e)) => return Err(e.into()),
         }
     }
 
-    // Convert our header buf into an httparse instance, and validate.
-    let status = httparse_req.parse(&buf)?;
-
-    ensure!(!status.is_partial(), "Malformed HTTP head");
+    // Parse the head straight out of the connection's read buffer, which may
+    // already hold it, reading more until it's complete.
+    let limit = opts.max_head_length.saturating_add(1);
+    let mut parsed = false;
+    let mut parse = |buf: &[u8]| parse_head(parser, buf, opts, version);
+    let read_head = future::poll_fn(|cx| reader.poll_head(cx, limit, &mut parsed, &mut parse));
+    let head = if let Some(timeout_duration) = opts.headers_timeout {
+        match timeout(timeout_duration, read_head).await {
+            Ok(head) => head?,
+            Err(TimeoutError { .. }) => None,
+        }
+    } else {
+        read_head.await?
+    };
 
-    // Convert httparse headers + body into a `http_types::Request` type.
-    let method = httparse_req.method;
-    let method = method.ok_or_else(|| format_err!("No method found"))?;
+    let mut req = match head {
+        Some(req) => req,
+        None => return Ok(None), /* EOF or timeout */
+    };
+    let version = req.version().unwrap_or(Version::Http1_1);
 
-    let version = httparse_req.version;
-    let version = version.ok_or_else(|| format_err!("No version found"))?;
+    // Determine how the body is framed, rejecting anything ambiguous to
+    // prevent request smuggling attacks.
+    //
+    // https://tools.ietf.org/html/rfc7230#section-3.3.3
+    let content_length = content_length(&req)?;
+    let is_chunked = match req.header(TRANSFER_ENCODING) {
+        Some(transfer_encoding) => {
+            ensure_status!(
+                version != Version::Http1_0,
+                400,
+                "Unexpected Transfer-Encoding header in HTTP/1.0 request"
+            );
+            ensure_status!(
+                content_length.is_none(),
+                400,
+                "Unexpected Content-Length header"
+            );
+            validate_transfer_encoding(transfer_encoding)?;
+            true
+        }
+        None => false,
+    };
 
-    ensure_eq!(
-        version,
-        HTTP_1_1_VERSION,
-        "Unsupported HTTP version 1.{}",
-        version
-    );
+    // Takes the place of the connection's read buffer while the body reader
+    // holds on to it.
+    let spare_reader = ReadBuffer::new(io.clone());
 
-    let url = url_from_httparse_req(&httparse_req)?;
+    // HTTP/1.0 clients don't know about 100-continue, so the expectation
+    // is ignored for them.
+    //
+    // https://tools.ietf.org/html/rfc7231#section-5.1.1
+    let expect = match req.header(EXPECT) {
+        Some(expect) if version == Version::Http1_1 => {
+            ensure_status!(
+                expect.as_str().eq_ignore_ascii_case(CONTINUE_HEADER_VALUE),
+                417,
+                "Unsupported expectation"
+            );
+            // The interim response is only written once the endpoint asks
+            // for it, either explicitly or by reading the body. This saves
+            // clients from uploading a body the endpoint responds without.
+            let expect = ExpectContinue::new(io);
+            req.ext_mut().insert(expect.clone());
+            Some(expect)
+        }
+        _ => None,
+    };
 
-    let mut req = Request::new(Method::from_str(method)?, url);
+    let body_timeout_error = Error::BodyTimeout(opts.body_timeout.unwrap_or_default());
 
-    req.set_version(Some(http_types::Version::Http1_1));
+    // Check for Transfer-Encoding
+    let framing = if is_chunked {
+        let trailer_sender = req.send_trailers();
+        let reader = mem::replace(reader, spare_reader);
+        let reader = ChunkedDecoder::new(reader, trailer_sender)
+            .with_max_body_len(opts.max_body_length)
+            .with_parse_mode(opts.parse_mode);
+        Framing::Chunked(reader)
+    } else if let Some(len) = content_length {
+        if let Some(max) = opts.max_body_length {
+            if len > max {
+                return Err(Error::BodyTooLarge(max).into_http());
+            }
+        }
+        let reader = mem::replace(reader, spare_reader);
+        Framing::Fixed(reader.take(len))
+    } else {
+        return Ok(Some((req, BodyReader::None)));
+    };
+
+    // The body reads straight out of the connection's read buffer.
+    let len = content_length.map(|len| len as usize);
+    let reader = IdleTimeout::new(framing, opts.body_timeout, body_timeout_error);
+    let reader = ReadNotifier::new(reader, expect, opts.auto_continue);
+    let (body, body_reader) = RequestBody::new(reader);
+    req.set_body(Body::from_reader(body, len));
+    Ok(Some((req, body_reader)))
+}
 
-    for header in httparse_req.headers.iter() {
-        req.append_header(header.name, std::str::from_utf8(header.value)?);
+/// Get the length of a request body from its Content-Length headers.
+///
+/// Several values are only accepted if they are all identical.
+fn content_length(req: &Request) -> http_types::Result<Option<u64>> {
+    let values = match req.header(CONTENT_LENGTH) {
+        Some(values) => values,
+        None => return Ok(None),
+    };
+
+    let mut content_length = None;
+    for value in values.iter().flat_map(|value| value.as_str().split(',')) {
+        let value = value.trim();
+        ensure_status!(
+            !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()),
+            400,
+            "Invalid Content-Length header"
+        );
+        let value: u64 = value.parse().status(400)?;
+        ensure_status!(
+            content_length.is_none() || content_length == Some(value),
+            400,
+            "Conflicting Content-Length headers"
+        );
+        content_length = Some(value);
     }
+    Ok(content_length)
+}
 
-    let content_length = ContentLength::from_headers(&req)?;
-    let transfer_encoding = req.header(TRANSFER_ENCODING);
+/// Check the Transfer-Encoding headers of a request.
+///
+/// Requests with a Transfer-Encoding have no other way to delimit their body,
+/// so chunked must be their final coding.
+fn validate_transfer_encoding(transfer_encoding: &HeaderValues) -> http_types::Result<()> {
+    let codings = transfer_encoding
+        .iter()
+        .flat_map(|value| value.as_str().split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .collect::<Vec<_>>();
+
+    for coding in &codings {
+        ensure_status!(
+            TRANSFER_CODINGS
+                .iter()
+                .any(|known| coding.eq_ignore_ascii_case(known)),
+            501,
+            "Unsupported transfer coding: {}",
+            coding
+        );
+    }
 
-    // Return a 400 status if both Content-Length and Transfer-Encoding headers
-    // are set to prevent request smuggling attacks.
-    //
-    // https://tools.ietf.org/html/rfc7230#section-3.3.3
-    http_types::ensure_status!(
-        content_length.is_none() || transfer_encoding.is_none(),
+    let chunked = codings
+        .iter()
+        .filter(|coding| coding.eq_ignore_ascii_case("chunked"))
+        .count();
+    ensure_status!(chunked <= 1, 400, "Chunked applied more than once");
+    ensure_status!(
+        codings
+            .last()
+            .map(|coding| coding.eq_ignore_ascii_case("chunked"))
+            .unwrap_or(false),
         400,
-        "Unexpected Content-Length header"
+        "Chunked must be the final transfer coding"
     );
+    Ok(())
+}
 
-    // Establish a channel to wait for the body to be read. This
-    // allows us to avoid sending 100-continue in situations that
-    // respond without reading the body, saving clients from uploading
-    // their body.
-    let (body_read_sender, body_read_receiver) = async_channel::bounded(1);
-
-    if Some(CONTINUE_HEADER_VALUE) == req.header(EXPECT).map(|h| h.as_str()) {
-        task::spawn(async move {
-            // If the client expects a 100-continue header, spawn a
-            // task to wait for the first read attempt on the body.
-            if let Ok(()) = body_read_receiver.recv().await {
-                io.write_all(CONTINUE_RESPONSE).await.ok();
-            };
-            // Since the sender is moved into the Body, this task will
-            // finish when the client disconnects, whether or not
-            // 100-continue was sent.
-        });
+/// Parse a request head out of the start of `buf`, which may also hold bytes
+/// past the end of the head.
+///
+/// Returns `None` if `buf` doesn't hold the whole head yet, or else the request
+/// and the length of its head.
+fn parse_head(
+    parser: &mut HeadParser,
+    buf: &[u8],
+    opts: &ServerOptions,
+    version: &mut Option<Version>,
+) -> http_types::Result<Option<(Request, usize)>> {
+    parser.parse(buf, |config, buf, headers| {
+        let mut httparse_req = httparse::Request::new(headers);
+        let status = config.parse_request(&mut httparse_req, buf);
+        *version = httparse_req.version.and_then(http_version);
+        Ok(match status? {
+            httparse::Status::Complete(len) => {
+                httparse::Status::Complete((len, request_from_httparse(&httparse_req, opts)))
+            }
+            httparse::Status::Partial => httparse::Status::Partial,
+        })
+    })
+}
+
+/// Build a request, without its body, from a parsed head.
+fn request_from_httparse(
+    httparse_req: &httparse::Request<'_, '_>,
+    opts: &ServerOptions,
+) -> http_types::Result<Request> {
+    let method = httparse_req.method;
+    let method = method.ok_or_else(|| format_err_status!(400, "No method found"))?;
+
+    let version = httparse_req.version;
+    let version = version.ok_or_else(|| format_err_status!(400, "No version found"))?;
+
+    let version = match http_version(version) {
+        Some(version) => version,
+        None => bail_status!(505, "Unsupported HTTP version 1.{}", version),
+    };
+
+    let (mut url, target) = url_from_httparse_req(httparse_req, &opts.default_host)?;
+    if let Some(info) = &opts.connection_info {
+        // An absolute-form target brings its own scheme.
+        if !matches!(target, RequestTarget::Absolute(_)) {
+            url.set_scheme(info.scheme())
+                .map_err(|_| format_err_status!(400, "Invalid URL scheme"))?;
+        }
     }
 
-    // Check for Transfer-Encoding
-    if transfer_encoding
-        .map(|te| te.as_str().eq_ignore_ascii_case("chunked"))
-        .unwrap_or(false)
-    {
-        let trailer_sender = req.send_trailers();
-        let reader = ChunkedDecoder::new(reader, trailer_sender);
-        let reader = Arc::new(Mutex::new(reader));
-        let reader_clone = reader.clone();
-        let reader = ReadNotifier::new(reader, body_read_sender);
-        let reader = BufReader::new(reader);
-        req.set_body(Body::from_reader(reader, None));
-        Ok(Some((req, BodyReader::Chunked(reader_clone))))
-    } else if let Some(len) = content_length {
-        let len = len.len();
-        let reader = Arc::new(Mutex::new(reader.take(len)));
-        req.set_body(Body::from_reader(
-            BufReader::new(ReadNotifier::new(reader.clone(), body_read_sender)),
-            Some(len as usize),
-        ));
-        Ok(Some((req, BodyReader::Fixed(reader))))
-    } else {
-        Ok(Some((req, BodyReader::None)))
+    let method = Method::from_str(method).map_err(|mut e| {
+        e.set_status(501);
+        e
+    })?;
+    let mut req = Request::new(method, url);
+
+    req.set_version(Some(version));
+    req.ext_mut().insert(target);
+    if let Some(info) = &opts.connection_info {
+        req.set_peer_addr(info.peer_addr());
+        req.set_local_addr(info.local_addr());
+        req.ext_mut().insert(info.clone());
     }
+
+    for header in httparse_req.headers.iter() {
+        req.append_header(header.name, std::str::from_utf8(header.value).status(400)?);
+    }
+
+    Ok(req)
 }
 
-fn url_from_httparse_req(req: &httparse::Request<'_, '_>) -> http_types::Result<Url> {
-    let path = req.path.ok_or_else(|| format_err!("No uri found"))?;
+/// The HTTP version of a minor version number returned from httparse.
+fn http_version(version: u8) -> Option<Version> {
+    match version {
+        HTTP_1_0_VERSION => Some(Version::Http1_0),
+        HTTP_1_1_VERSION => Some(Version::Http1_1),
+        _ => None,
+    }
+}
 
-    let host = req
+/// Build the URL of a request from its request-target and Host header.
+///
+/// The Host header is required for HTTP/1.1 requests, may only be sent once,
+/// and must agree with the request-target if that has an authority.
+///
+/// https://tools.ietf.org/html/rfc7230#section-5.4
+fn url_from_httparse_req(
+    req: &httparse::Request<'_, '_>,
+    default_host: &str,
+) -> http_types::Result<(Url, RequestTarget)> {
+    let path = req
+        .path
+        .ok_or_else(|| format_err_status!(400, "No uri found"))?;
+
+    let mut hosts = req
         .headers
         .iter()
-        .find(|x| x.name.eq_ignore_ascii_case("host"))
-        .ok_or_else(|| format_err!("Mandatory Host header missing"))?
-        .value;
-
-    let host = std::str::from_utf8(host)?;
-
-    if path.starts_with("http://") || path.starts_with("https://") {
-        Ok(Url::parse(path)?)
+        .filter(|x| x.name.eq_ignore_ascii_case("host"));
+    let host = match (hosts.next(), hosts.next()) {
+        (Some(header), None) => std::str::from_utf8(header.value).status(400)?,
+        (Some(_), Some(_)) => bail_status!(400, "Duplicate Host header"),
+        // The Host header is only mandatory since HTTP/1.1.
+        (None, _) if req.version == Some(HTTP_1_0_VERSION) => default_host,
+        (None, _) => bail_status!(400, "Mandatory Host header missing"),
+    };
+    ensure_status!(is_valid_host(host), 400, "Invalid Host header");
+    let host_url = Url::parse(&format!("http://{}/", host)).status(400)?;
+
+    if req.method.unwrap().eq_ignore_ascii_case("connect") {
+        // CONNECT requests only ever use the authority form.
+        let (name, port) = split_host_port(path);
+        ensure_status!(
+            !name.is_empty()
+                && !name.contains(|c| "/?#@".contains(c))
+                && port
+                    .map(|port| port.parse::<u16>().is_ok())
+                    .unwrap_or(false),
+            400,
+            "CONNECT request-target must be a host and port"
+        );
+        let url = Url::parse(&format!("http://{}/", path)).status(400)?;
+        ensure_status!(
+            host_matches(&url, host),
+            400,
+            "Host header doesn't match the request-target"
+        );
+        Ok((url, RequestTarget::Authority(path.to_owned())))
+    } else if path.starts_with("http://") || path.starts_with("https://") {
+        let url = Url::parse(path).status(400)?;
+        ensure_status!(
+            host_matches(&url, host),
+            400,
+            "Host header doesn't match the request-target"
+        );
+        Ok((url, RequestTarget::Absolute(path.to_owned())))
     } else if path.starts_with('/') {
-        Ok(Url::parse(&format!("http://{}{}", host, path))?)
-    } else if req.method.unwrap().eq_ignore_ascii_case("connect") {
-        Ok(Url::parse(&format!("http://{}/", path))?)
+        let url = Url::parse(&format!("http://{}{}", host, path)).status(400)?;
+        Ok((url, RequestTarget::Origin(path.to_owned())))
+    } else if path == "*" {
+        ensure_status!(
+            req.method.unwrap().eq_ignore_ascii_case("options"),
+            400,
+            "Asterisk request-target is only allowed for OPTIONS requests"
+        );
+        Ok((host_url, RequestTarget::Asterisk))
     } else {
-        Err(format_err!("unexpected uri format"))
+        Err(format_err_status!(400, "unexpected uri format"))
+    }
+}
+
+/// Split the port off a host, if it has one.
+fn split_host_port(host: &str) -> (&str, Option<&str>) {
+    match host.rsplit_once(':') {
+        // The colons of an IPv6 address are inside brackets.
+        Some((name, port)) if !port.contains(']') => (name, Some(port)),
+        _ => (host, None),
+    }
+}
+
+/// Whether a Host header is a `host[:port]`, with nothing else around it.
+///
+/// https://tools.ietf.org/html/rfc7230#section-5.4
+fn is_valid_host(host: &str) -> bool {
+    let (name, port) = split_host_port(host);
+    let valid_name = match name.strip_prefix('[') {
+        Some(ip) => {
+            ip.len() > 1
+                && ip.ends_with(']')
+                && ip[..ip.len() - 1]
+                    .bytes()
+                    .all(|b| b.is_ascii_hexdigit() || b == b':' || b == b'.')
+        }
+        None => {
+            !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-._~%!$&'()*+,;=".contains(&b))
+        }
+    };
+    let valid_port = match port {
+        Some(port) => {
+            port.is_empty()
+                || (port.bytes().all(|b| b.is_ascii_digit()) && port.parse::<u16>().is_ok())
+        }
+        None => true,
+    };
+    valid_name && valid_port
+}
+
+/// Whether a Host header names the same host and port as a URL. A Host
+/// without a port stands for the default port of the URL's scheme.
+fn host_matches(url: &Url, host: &str) -> bool {
+    let (name, port) = split_host_port(host);
+    let port = match port {
+        Some(port) if !port.is_empty() => port.parse().ok(),
+        _ => default_port(url.scheme()),
+    };
+    url.host_str()
+        .map(|url_host| url_host.eq_ignore_ascii_case(name))
+        .unwrap_or(false)
+        && port == url.port_or_known_default()
+}
+
+/// The default port of a URL scheme.
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" => Some(80),
+        "https" => Some(443),
+        _ => None,
     }
 }
 
@@ -169,7 +488,7 @@ mod tests {
     use super::*;
 
     fn httparse_req(buf: &str, f: impl Fn(httparse::Request<'_, '_>)) {
-        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut headers = [httparse::EMPTY_HEADER; crate::MAX_HEADERS];
         let mut res = httparse::Request::new(&mut headers[..]);
         res.parse(buf.as_bytes()).unwrap();
         f(res)
@@ -180,8 +499,12 @@ mod tests {
         httparse_req(
             "CONNECT server.example.com:443 HTTP/1.1\r\nHost: server.example.com:443\r\n",
             |req| {
-                let url = url_from_httparse_req(&req).unwrap();
+                let (url, target) = url_from_httparse_req(&req, "localhost").unwrap();
                 assert_eq!(url.as_str(), "http://server.example.com:443/");
+                assert_eq!(
+                    target,
+                    RequestTarget::Authority("server.example.com:443".into())
+                );
             },
         );
     }
@@ -191,7 +514,7 @@ mod tests {
         httparse_req(
             "GET /some/resource HTTP/1.1\r\nHost: server.example.com:443\r\n",
             |req| {
-                let url = url_from_httparse_req(&req).unwrap();
+                let (url, _) = url_from_httparse_req(&req, "localhost").unwrap();
                 assert_eq!(url.as_str(), "http://server.example.com:443/some/resource");
             },
         )
@@ -200,23 +523,171 @@ mod tests {
     #[test]
     fn url_for_host_plus_absolute_url() {
         httparse_req(
-            "GET http://domain.com/some/resource HTTP/1.1\r\nHost: server.example.com\r\n",
+            "GET http://domain.com/some/resource HTTP/1.1\r\nHost: Domain.com:80\r\n",
             |req| {
-                let url = url_from_httparse_req(&req).unwrap();
-                assert_eq!(url.as_str(), "http://domain.com/some/resource"); // host header MUST be ignored according to spec
+                let (url, target) = url_from_httparse_req(&req, "localhost").unwrap();
+                assert_eq!(url.as_str(), "http://domain.com/some/resource");
+                assert_eq!(
+                    target,
+                    RequestTarget::Absolute("http://domain.com/some/resource".into())
+                );
             },
         )
     }
 
+    #[test]
+    fn url_for_conflicting_absolute_url() {
+        for host in &["server.example.com", "domain.com:8080", "[::1]"] {
+            httparse_req(
+                &format!(
+                    "GET http://domain.com/some/resource HTTP/1.1\r\nHost: {}\r\n",
+                    host
+                ),
+                |req| {
+                    assert!(url_from_httparse_req(&req, "localhost").is_err());
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn url_for_duplicate_or_invalid_host() {
+        httparse_req(
+            "GET / HTTP/1.1\r\nHost: example.com\r\nHost: example.com\r\n",
+            |req| {
+                assert!(url_from_httparse_req(&req, "localhost").is_err());
+            },
+        );
+        httparse_req("GET / HTTP/1.1\r\nHost: user@example.com\r\n", |req| {
+            assert!(url_from_httparse_req(&req, "localhost").is_err());
+        });
+        httparse_req("GET / HTTP/1.1\r\nHost: example.com/path\r\n", |req| {
+            assert!(url_from_httparse_req(&req, "localhost").is_err());
+        });
+    }
+
+    #[test]
+    fn url_for_asterisk() {
+        httparse_req("OPTIONS * HTTP/1.1\r\nHost: example.com\r\n", |req| {
+            let (url, target) = url_from_httparse_req(&req, "localhost").unwrap();
+            assert_eq!(url.as_str(), "http://example.com/");
+            assert_eq!(target, RequestTarget::Asterisk);
+        });
+        httparse_req("GET * HTTP/1.1\r\nHost: example.com\r\n", |req| {
+            assert!(url_from_httparse_req(&req, "localhost").is_err());
+        });
+    }
+
+    #[test]
+    fn url_for_malformed_connect() {
+        for target in &[
+            "server.example.com",
+            "server.example.com:",
+            "/path",
+            "a@b:443",
+        ] {
+            httparse_req(
+                &format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target),
+                |req| {
+                    assert!(url_from_httparse_req(&req, "localhost").is_err());
+                },
+            )
+        }
+    }
+
     #[test]
     fn url_for_conflicting_connect() {
         httparse_req(
             "CONNECT server.example.com:443 HTTP/1.1\r\nHost: conflicting.host\r\n",
             |req| {
-                let url = url_from_httparse_req(&req).unwrap();
-                assert_eq!(url.as_str(), "http://server.example.com:443/");
+                assert!(url_from_httparse_req(&req, "localhost").is_err());
             },
-        )
+        );
+        httparse_req(
+            "CONNECT server.example.com:443 HTTP/1.1\r\nHost: server.example.com:8443\r\n",
+            |req| {
+                assert!(url_from_httparse_req(&req, "localhost").is_err());
+            },
+        );
+        httparse_req(
+            "CONNECT server.example.com:443 HTTP/1.1\r\nHost: server.example.com\r\n",
+            |req| {
+                assert!(url_from_httparse_req(&req, "localhost").is_err());
+            },
+        );
+        httparse_req(
+            "CONNECT server.example.com:80 HTTP/1.1\r\nHost: server.example.com\r\n",
+            |req| {
+                let (url, _) = url_from_httparse_req(&req, "localhost").unwrap();
+                assert_eq!(url.as_str(), "http://server.example.com/");
+            },
+        );
+    }
+
+    #[test]
+    fn url_for_absolute_target_with_default_port() {
+        httparse_req(
+            "GET https://server.example.com/ HTTP/1.1\r\nHost: server.example.com\r\n",
+            |req| {
+                let (url, _) = url_from_httparse_req(&req, "localhost").unwrap();
+                assert_eq!(url.as_str(), "https://server.example.com/");
+            },
+        );
+        httparse_req(
+            "GET https://server.example.com:8443/ HTTP/1.1\r\nHost: server.example.com\r\n",
+            |req| {
+                assert!(url_from_httparse_req(&req, "localhost").is_err());
+            },
+        );
+    }
+
+    #[test]
+    fn url_for_invalid_host() {
+        for host in &[
+            "example.com#x",
+            "example.com/p",
+            "example.com?q",
+            "user@example.com",
+            "example.com:+80",
+            "example.com:99999",
+            "[::1",
+            "",
+        ] {
+            let buf = format!("GET / HTTP/1.1\r\nHost: {}\r\n", host);
+            httparse_req(&buf, |req| {
+                assert!(
+                    url_from_httparse_req(&req, "localhost").is_err(),
+                    "{}",
+                    host
+                );
+            });
+        }
+        for host in &[
+            "example.com",
+            "example.com:8080",
+            "127.0.0.1:80",
+            "[::1]:8080",
+        ] {
+            let buf = format!("GET / HTTP/1.1\r\nHost: {}\r\n", host);
+            httparse_req(&buf, |req| {
+                assert!(url_from_httparse_req(&req, "localhost").is_ok(), "{}", host);
+            });
+        }
+    }
+
+    #[test]
+    fn url_for_http_1_0_without_host() {
+        httparse_req("GET /some/resource HTTP/1.0\r\n", |req| {
+            let (url, _) = url_from_httparse_req(&req, "localhost").unwrap();
+            assert_eq!(url.as_str(), "http://localhost/some/resource");
+        });
+        httparse_req("GET /some/resource HTTP/1.0\r\n", |req| {
+            let (url, _) = url_from_httparse_req(&req, "example.com:8080").unwrap();
+            assert_eq!(url.as_str(), "http://example.com:8080/some/resource");
+        });
+        httparse_req("GET /some/resource HTTP/1.1\r\n", |req| {
+            assert!(url_from_httparse_req(&req, "localhost").is_err());
+        });
     }
 
     #[test]
@@ -224,7 +695,7 @@ mod tests {
         httparse_req(
             "GET not-a-url HTTP/1.1\r\nHost: server.example.com\r\n",
             |req| {
-                assert!(url_from_httparse_req(&req).is_err());
+                assert!(url_from_httparse_req(&req, "localhost").is_err());
             },
         )
     }
@@ -234,7 +705,7 @@ mod tests {
         httparse_req(
             "GET //double/slashes HTTP/1.1\r\nHost: server.example.com:443\r\n",
             |req| {
-                let url = url_from_httparse_req(&req).unwrap();
+                let (url, _) = url_from_httparse_req(&req, "localhost").unwrap();
                 assert_eq!(
                     url.as_str(),
                     "http://server.example.com:443//double/slashes"
@@ -247,7 +718,7 @@ mod tests {
         httparse_req(
             "GET ///triple/slashes HTTP/1.1\r\nHost: server.example.com:443\r\n",
             |req| {
-                let url = url_from_httparse_req(&req).unwrap();
+                let (url, _) = url_from_httparse_req(&req, "localhost").unwrap();
                 assert_eq!(
                     url.as_str(),
                     "http://server.example.com:443///triple/slashes"
@@ -261,7 +732,7 @@ mod tests {
         httparse_req(
             "GET /foo?bar=1 HTTP/1.1\r\nHost: server.example.com:443\r\n",
             |req| {
-                let url = url_from_httparse_req(&req).unwrap();
+                let (url, _) = url_from_httparse_req(&req, "localhost").unwrap();
                 assert_eq!(url.as_str(), "http://server.example.com:443/foo?bar=1");
             },
         )
@@ -272,7 +743,7 @@ mod tests {
         httparse_req(
             "GET /foo?bar=1#anchor HTTP/1.1\r\nHost: server.example.com:443\r\n",
             |req| {
-                let url = url_from_httparse_req(&req).unwrap();
+                let (url, _) = url_from_httparse_req(&req, "localhost").unwrap();
                 assert_eq!(
                     url.as_str(),
                     "http://server.example.com:443/foo?bar=1#anchor"
@@ -2,15 +2,17 @@
 
 use std::io::Write;
 use std::pin::Pin;
-use std::time::SystemTime;
 
+use async_std::future::{poll_fn, Future};
 use async_std::io::{self, Cursor, Read};
 use async_std::task::{Context, Poll};
-use http_types::headers::{CONTENT_LENGTH, DATE, TRANSFER_ENCODING};
-use http_types::{Method, Response};
+use http_types::headers::{CONTENT_LENGTH, DATE, TRAILER, TRANSFER_ENCODING};
+use http_types::{Body, Method, Response, Trailers, Version};
 
 use crate::body_encoder::BodyEncoder;
-use crate::date::fmt_http_date;
+use crate::chunked::{trailer_fields, ChunkedEncoder, PendingTrailers, DEFAULT_CHUNK_SIZE};
+use crate::corked_write::write_corked;
+use crate::date::http_date_now;
 use crate::read_to_end;
 use crate::EncoderState;
 
@@ -20,9 +22,15 @@ pub struct Encoder {
     response: Response,
     state: EncoderState,
     method: Method,
+    send_trailers: bool,
+    trailers: Option<PendingTrailers>,
+    chunk_size: usize,
+    coalesce_chunks: bool,
 }
 
 impl Read for Encoder {
+    // `io::Error::other` would require Rust 1.74.
+    #[allow(unknown_lints, clippy::io_other_error)]
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
@@ -30,17 +38,11 @@ impl Read for Encoder {
     ) -> Poll<io::Result<usize>> {
         loop {
             self.state = match self.state {
-                EncoderState::Start => EncoderState::Head(self.compute_head()?),
+                EncoderState::Start => EncoderState::Head(self.compute_head(cx)?),
 
                 EncoderState::Head(ref mut cursor) => {
                     read_to_end!(Pin::new(cursor).poll_read(cx, buf));
-
-                    if self.method == Method::Head {
-                        EncoderState::End
-                    } else {
-                        let res_len = self.response.len();
-                        EncoderState::Body(BodyEncoder::new(self.response.take_body()), 0, res_len)
-                    }
+                    self.body_state()
                 }
 
                 EncoderState::Body(ref mut encoder, ref mut n_written, res_len) => {
@@ -72,37 +74,165 @@ impl Read for Encoder {
 
 impl Encoder {
     /// Create a new instance of Encoder.
+    ///
+    /// The response is encoded as HTTP/1.0 if its version is set to
+    /// `Version::Http1_0`, and as HTTP/1.1 otherwise.
     pub fn new(response: Response, method: Method) -> Self {
         Self {
             method,
             response,
             state: EncoderState::Start,
+            send_trailers: true,
+            trailers: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            coalesce_chunks: false,
+        }
+    }
+
+    /// Set whether trailers sent through the response's trailer channel are
+    /// encoded. Defaults to true.
+    ///
+    /// Responses with trailers are sent with chunked encoding, even if their
+    /// length is known. Otherwise, and for HTTP/1.0 responses, the trailers
+    /// are dropped.
+    ///
+    /// Trailers that have already been sent when the head is encoded are
+    /// announced in the `Trailer` header. Trailers sent later aren't known in
+    /// time, so callers that want them announced must set the `Trailer`
+    /// header on the response themselves. It's removed when the trailers are
+    /// dropped.
+    pub fn with_trailers(mut self, send_trailers: bool) -> Self {
+        self.send_trailers = send_trailers;
+        self
+    }
+
+    /// Set the maximum size of the data in each chunk of a chunked body.
+    /// Defaults to 8 KiB.
+    ///
+    /// Sizes are clamped to between 1 byte and 16 MiB.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Set whether body reads that complete immediately one after another are
+    /// combined into a single chunk, up to the chunk size. Defaults to false,
+    /// which sends a chunk for every read.
+    ///
+    /// A chunk is still sent as soon as the body has no more data ready, so
+    /// streaming bodies aren't held back.
+    pub fn with_chunk_coalescing(mut self, coalesce_chunks: bool) -> Self {
+        self.coalesce_chunks = coalesce_chunks;
+        self
+    }
+
+    /// Write the encoded response straight to `writer`, returning the number
+    /// of bytes written.
+    ///
+    /// Unlike copying the encoder as a reader, this writes the head together
+    /// with the first bytes of the body using vectored writes, so a response
+    /// with a small body is sent in a single write. The writer is flushed at
+    /// the end.
+    pub async fn write_to<W>(&mut self, writer: &mut W) -> io::Result<u64>
+    where
+        W: io::Write + Unpin + ?Sized,
+    {
+        if let EncoderState::Start = self.state {
+            let head = poll_fn(|cx| Poll::Ready(self.compute_head(cx))).await?;
+            let head = head.into_inner();
+            self.state = self.body_state();
+            write_corked(&head, self, writer).await
+        } else {
+            io::copy(self, writer).await
         }
     }
 
-    fn finalize_headers(&mut self) {
-        // If the body isn't streaming, we can set the content-length ahead of time. Else we need to
-        // send all items in chunks.
-        if let Some(len) = self.response.len() {
-            self.response.insert_header(CONTENT_LENGTH, len.to_string());
+    /// The state after the head has been written.
+    fn body_state(&mut self) -> EncoderState {
+        if self.method == Method::Head {
+            return EncoderState::End;
+        }
+        let mut res_len = self.response.len();
+        let body = self.response.take_body();
+        let encoder = if self.is_http_1_0() {
+            // HTTP/1.0 bodies are delimited by closing the connection.
+            BodyEncoder::Fixed(body)
+        } else if let Some(trailers) = self.trailers.take() {
+            res_len = None;
+            BodyEncoder::Chunked(self.chunked(body).with_trailers(trailers))
         } else {
+            BodyEncoder::new(body, |body| self.chunked(body))
+        };
+        EncoderState::Body(Box::new(encoder), 0, res_len)
+    }
+
+    fn chunked(&self, body: Body) -> ChunkedEncoder<Body> {
+        ChunkedEncoder::new(body)
+            .with_chunk_size(self.chunk_size)
+            .with_coalescing(self.coalesce_chunks)
+    }
+
+    fn is_http_1_0(&self) -> bool {
+        self.response.version() == Some(Version::Http1_0)
+    }
+
+    fn finalize_headers(&mut self, cx: &mut Context<'_>) {
+        // Trailers can only follow a chunked body, so responses that carry them
+        // are always chunked.
+        if self.send_trailers && self.response.has_trailers() && !self.is_http_1_0() {
+            let mut receiver = self.response.recv_trailers();
+            self.trailers = Some(match Pin::new(&mut receiver).poll(cx) {
+                Poll::Ready(trailers) => {
+                    self.announce_trailers(trailers.as_ref());
+                    PendingTrailers::Received(trailers)
+                }
+                Poll::Pending => PendingTrailers::Receiving(receiver),
+            });
+            self.response.remove_header(CONTENT_LENGTH);
             self.response.insert_header(TRANSFER_ENCODING, "chunked");
+        } else {
+            self.response.remove_header(TRAILER);
+
+            // If the body isn't streaming, we can set the content-length ahead of time. Else we
+            // need to send all items in chunks, unless the peer speaks HTTP/1.0 which has no
+            // chunked encoding.
+            if let Some(len) = self.response.len() {
+                self.response.insert_header(CONTENT_LENGTH, len.to_string());
+            } else if !self.is_http_1_0() {
+                self.response.insert_header(TRANSFER_ENCODING, "chunked");
+            }
         }
 
         if self.response.header(DATE).is_none() {
-            let date = fmt_http_date(SystemTime::now());
-            self.response.insert_header(DATE, date);
+            self.response.insert_header(DATE, http_date_now());
+        }
+    }
+
+    /// List the names of trailers that were sent before the head in the
+    /// `Trailer` header.
+    fn announce_trailers(&mut self, trailers: Option<&Trailers>) {
+        let names = trailers
+            .map(|trailers| trailer_fields(trailers))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            self.response.remove_header(TRAILER);
+        } else {
+            self.response.insert_header(TRAILER, names.join(", "));
         }
     }
 
     /// Encode the headers to a buffer, the first time we poll.
-    fn compute_head(&mut self) -> io::Result<Cursor<Vec<u8>>> {
+    fn compute_head(&mut self, cx: &mut Context<'_>) -> io::Result<Cursor<Vec<u8>>> {
         let mut head = Vec::with_capacity(128);
         let reason = self.response.status().canonical_reason();
         let status = self.response.status();
-        write!(head, "HTTP/1.1 {} {}\r\n", status, reason)?;
+        let version = if self.is_http_1_0() { "1.0" } else { "1.1" };
+        write!(head, "HTTP/{} {} {}\r\n", version, status, reason)?;
 
-        self.finalize_headers();
+        self.finalize_headers(cx);
         let mut headers = self.response.iter().collect::<Vec<_>>();
         headers.sort_unstable_by_key(|(h, _)| h.as_str());
         for (header, values) in headers {
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use async_std::future;
+use async_std::io::{self, Write};
+use futures_core::ready;
+
+const CONTINUE_RESPONSE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// Control over a request's `Expect: 100-continue`.
+///
+/// Requests that expect a `100 Continue` carry this in their extensions.
+/// By default the interim response is sent the first time the body is read,
+/// which can be turned off with [`ServerOptions::with_auto_continue`]. Reads
+/// of the body then wait for [`send`](ExpectContinue::send), or for the
+/// response to start. It's always written ahead of the body being read or
+/// the final response, never in between.
+/// Endpoints that respond without reading the body spare the client from
+/// uploading it; the connection is then closed rather than drained.
+///
+/// [`ServerOptions::with_auto_continue`]: super::ServerOptions::with_auto_continue
+///
+/// # Example
+///
+/// ```
+/// use async_h1::server::ExpectContinue;
+/// use http_types::{Request, Response, StatusCode};
+///
+/// async fn endpoint(req: Request) -> http_types::Result<Response> {
+///     if req.len().unwrap_or(0) > 1024 * 1024 {
+///         return Ok(Response::new(StatusCode::PayloadTooLarge));
+///     }
+///     if let Some(expect) = req.ext().get::<ExpectContinue>() {
+///         expect.send();
+///     }
+///     Ok(Response::new(StatusCode::Ok))
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ExpectContinue {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    writer: Box<dyn Write + Send + Unpin>,
+    /// Whether the endpoint asked for `100 Continue`.
+    requested: bool,
+    /// Whether the final response has started, after which it's too late.
+    closed: bool,
+    /// How much of the interim response has been written.
+    written: usize,
+    /// The body reader waiting for `100 Continue` to be requested.
+    waker: Option<Waker>,
+}
+
+impl fmt::Debug for ExpectContinue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("ExpectContinue")
+            .field("requested", &state.requested)
+            .field("closed", &state.closed)
+            .field("written", &state.written)
+            .finish()
+    }
+}
+
+impl ExpectContinue {
+    pub(crate) fn new(writer: impl Write + Send + Unpin + 'static) -> Self {
+        let state = State {
+            writer: Box::new(writer),
+            requested: false,
+            closed: false,
+            written: 0,
+            waker: None,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Tell the client to send the body with `100 Continue`.
+    ///
+    /// It's written before the body is next read, or before the response if
+    /// that comes first. Does nothing if it has already been sent, or once
+    /// the response has started.
+    pub fn send(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.requested && !state.closed {
+            state.requested = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Whether `100 Continue` has been sent to the client, or is about to be
+    /// written ahead of the body or the response.
+    pub fn is_sent(&self) -> bool {
+        self.state.lock().unwrap().requested
+    }
+
+    /// Write `100 Continue` if it was requested, or wait for it to be
+    /// requested. Used by the body reader before each read.
+    ///
+    /// Once the response has started without it, the body reader goes ahead
+    /// without sending it.
+    pub(crate) fn poll_write(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.requested {
+            if state.closed {
+                return Poll::Ready(Ok(()));
+            }
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        state.poll_write(cx)
+    }
+
+    /// Finish writing `100 Continue` before the final response, or make sure
+    /// it's never sent if it wasn't requested.
+    pub(crate) async fn finish(&self) -> io::Result<()> {
+        future::poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            if !state.requested {
+                return Poll::Ready(Ok(()));
+            }
+            state.poll_write(cx)
+        })
+        .await
+    }
+}
+
+impl State {
+    fn poll_write(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < CONTINUE_RESPONSE.len() {
+            let buf = &CONTINUE_RESPONSE[self.written..];
+            let n = ready!(Pin::new(&mut self.writer).poll_write(cx, buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use async_std::io::{self, BufRead, IoSlice, Read, Write};
+use async_std::task::{self, Context, Poll};
+
+use crate::Error;
+
+type Timer = Pin<Box<dyn Future<Output = ()> + Send + Sync + 'static>>;
+
+/// IdleTimeout forwards [`async_std::io::Read`], [`async_std::io::BufRead`]
+/// and [`async_std::io::Write`] to an inner stream, failing with a
+/// `TimedOut` error wrapping `error` if the stream makes no progress for
+/// longer than the configured duration. Once timed out, every later poll
+/// fails with the same error.
+#[pin_project::pin_project]
+pub(crate) struct IdleTimeout<T> {
+    #[pin]
+    inner: T,
+    duration: Option<Duration>,
+    error: Error,
+    timer: Option<Timer>,
+    timed_out: bool,
+}
+
+impl<T> fmt::Debug for IdleTimeout<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdleTimeout")
+            .field("duration", &self.duration)
+            .field("error", &self.error)
+            .field("timed_out", &self.timed_out)
+            .finish()
+    }
+}
+
+impl<T> IdleTimeout<T> {
+    pub(crate) fn new(inner: T, duration: Option<Duration>, error: Error) -> Self {
+        Self {
+            inner,
+            duration,
+            error,
+            timer: None,
+            timed_out: false,
+        }
+    }
+
+    /// Whether the stream has timed out.
+    pub(crate) fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Get a reference to the inner stream.
+    pub(crate) fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Unwrap the inner stream.
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// Start the timer if it isn't running yet, and fail if it has expired.
+fn poll_timer(
+    timer: &mut Option<Timer>,
+    timed_out: &mut bool,
+    duration: Option<Duration>,
+    error: Error,
+    cx: &mut Context<'_>,
+) -> io::Result<()> {
+    if let Some(duration) = duration {
+        let running = timer.get_or_insert_with(|| Box::pin(task::sleep(duration)));
+        if running.as_mut().poll(cx).is_ready() {
+            // A finished timer can't be polled again.
+            *timer = None;
+            *timed_out = true;
+            return Err(timed_out_error(error));
+        }
+    }
+    Ok(())
+}
+
+/// Reset the timer whenever the inner stream makes progress, and keep
+/// failing once it has timed out.
+fn track_progress<T>(
+    poll: impl FnOnce(&mut Context<'_>) -> Poll<io::Result<T>>,
+    timer: &mut Option<Timer>,
+    timed_out: &mut bool,
+    duration: Option<Duration>,
+    error: Error,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<T>> {
+    if *timed_out {
+        return Poll::Ready(Err(timed_out_error(error)));
+    }
+    match poll(cx) {
+        Poll::Pending => {
+            poll_timer(timer, timed_out, duration, error, cx)?;
+            Poll::Pending
+        }
+        ready => {
+            *timer = None;
+            ready
+        }
+    }
+}
+
+fn timed_out_error(error: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, error)
+}
+
+impl<T: Read> Read for IdleTimeout<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let inner = this.inner;
+        let poll = |cx: &mut Context<'_>| inner.poll_read(cx, buf);
+        track_progress(
+            poll,
+            this.timer,
+            this.timed_out,
+            *this.duration,
+            *this.error,
+            cx,
+        )
+    }
+}
+
+impl<T: BufRead> BufRead for IdleTimeout<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.project();
+        let inner = this.inner;
+        let poll = |cx: &mut Context<'_>| inner.poll_fill_buf(cx);
+        track_progress(
+            poll,
+            this.timer,
+            this.timed_out,
+            *this.duration,
+            *this.error,
+            cx,
+        )
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.project().inner.consume(amt)
+    }
+}
+
+impl<T: Write> Write for IdleTimeout<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let inner = this.inner;
+        let poll = |cx: &mut Context<'_>| inner.poll_write(cx, buf);
+        track_progress(
+            poll,
+            this.timer,
+            this.timed_out,
+            *this.duration,
+            *this.error,
+            cx,
+        )
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let inner = this.inner;
+        let poll = |cx: &mut Context<'_>| inner.poll_write_vectored(cx, bufs);
+        track_progress(
+            poll,
+            this.timer,
+            this.timed_out,
+            *this.duration,
+            *this.error,
+            cx,
+        )
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let inner = this.inner;
+        let poll = |cx: &mut Context<'_>| inner.poll_flush(cx);
+        track_progress(
+            poll,
+            this.timer,
+            this.timed_out,
+            *this.duration,
+            *this.error,
+            cx,
+        )
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::prelude::*;
+
+    struct PendingWriter;
+
+    impl Write for PendingWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Pending
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn stalled_write_times_out() {
+        task::block_on(async {
+            let duration = Duration::from_millis(50);
+            let error = Error::WriteTimeout(duration);
+            let mut writer = IdleTimeout::new(PendingWriter, Some(duration), error);
+
+            let err = writer.write_all(b"hello").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert_eq!(err.into_inner().unwrap().downcast_ref(), Some(&error));
+        });
+    }
+
+    #[test]
+    fn stays_timed_out() {
+        task::block_on(async {
+            let duration = Duration::from_millis(50);
+            let error = Error::WriteTimeout(duration);
+            let mut writer = IdleTimeout::new(PendingWriter, Some(duration), error);
+
+            writer.write_all(b"hello").await.unwrap_err();
+            let err = writer.write_all(b"hello").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert!(writer.timed_out());
+        });
+    }
+
+    #[test]
+    fn progress_resets_the_timer() {
+        task::block_on(async {
+            let duration = Duration::from_millis(50);
+            let error = Error::BodyTimeout(duration);
+            let reader = io::Cursor::new(b"hello".to_vec());
+            let mut reader = IdleTimeout::new(reader, Some(duration), error);
+
+            let mut output = String::new();
+            reader.read_to_string(&mut output).await.unwrap();
+            assert_eq!(output, "hello");
+        });
+    }
+}
@@ -1,36 +1,482 @@
 //! Process HTTP connections on the server.
 
-use async_std::future::{timeout, Future, TimeoutError};
-use async_std::io::{self, Read, Write};
-use http_types::headers::{CONNECTION, UPGRADE};
+use async_std::future::Future;
+use async_std::io::{self, Read, ReadExt, Write};
+use http_types::headers::{CONNECTION, TE, UPGRADE};
 use http_types::upgrade::Connection;
-use http_types::{Request, Response, StatusCode};
-use std::{marker::PhantomData, time::Duration};
+use http_types::{Method, Request, Response, StatusCode, Version};
+use std::any::Any;
+use std::sync::Arc;
+use std::{fmt, marker::PhantomData, time::Duration};
+
+use crate::chunked::DEFAULT_CHUNK_SIZE;
+use crate::parse::HeadParser;
+use crate::read_buffer::ReadBuffer;
+use crate::{Error, ParseMode, MAX_HEADERS, MAX_HEAD_LENGTH};
+
 mod body_reader;
+mod catch_panic;
+mod connection_info;
 mod decode;
 mod encode;
+mod expect;
+mod idle_timeout;
+mod proxy_protocol;
+mod serve;
+mod shutdown;
+mod target;
 
-pub use decode::decode;
+use catch_panic::{catch_panic, panic_message};
+pub use connection_info::ConnectionInfo;
+use decode::decode_with_idle_timeout;
+pub use decode::{decode, decode_with_opts};
 pub use encode::Encoder;
+pub use expect::ExpectContinue;
+use idle_timeout::IdleTimeout;
+use proxy_protocol::read_proxy_header;
+pub use serve::serve;
+pub use shutdown::Shutdown;
+pub use target::RequestTarget;
+
+const KEEP_ALIVE: &str = "keep-alive";
+
+/// The host used for HTTP/1.0 requests that don't send a Host header.
+const DEFAULT_HOST: &str = "localhost";
+
+/// A function that turns an error into the response sent to the client.
+#[derive(Clone)]
+struct ErrorHandler(Arc<dyn Fn(&http_types::Error) -> Response + Send + Sync + 'static>);
+
+impl fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorHandler")
+    }
+}
+
+/// A function that is told about panics caught in the endpoint.
+#[derive(Clone)]
+struct PanicHandler(Arc<dyn Fn(&PanicPayload) + Send + Sync + 'static>);
+
+/// The payload of a caught panic.
+type PanicPayload = dyn Any + Send;
+
+impl fmt::Debug for PanicHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PanicHandler")
+    }
+}
 
 /// Configure the server.
 #[derive(Debug, Clone)]
 pub struct ServerOptions {
     /// Timeout to handle headers. Defaults to 60s.
     headers_timeout: Option<Duration>,
+    /// Timeout to wait for a subsequent request on a kept-alive connection.
+    /// Defaults to none, leaving only the headers timeout.
+    keep_alive_timeout: Option<Duration>,
+    /// Maximum number of requests served on a connection. Defaults to none.
+    max_requests: Option<usize>,
+    /// Timeout for the request body to make progress. Defaults to none.
+    body_timeout: Option<Duration>,
+    /// Timeout for the response write to make progress. Defaults to none.
+    write_timeout: Option<Duration>,
+    /// Maximum length of a request body in bytes. Defaults to none.
+    max_body_length: Option<u64>,
+    /// Maximum number of unread request body bytes discarded to keep a
+    /// connection alive. Defaults to none.
+    max_drain_length: Option<u64>,
+    /// Signal to gracefully shut down the connection. Defaults to none.
+    shutdown: Option<Shutdown>,
+    /// Maximum number of concurrent connections in `serve`. Defaults to none.
+    max_connections: Option<usize>,
+    /// Builds responses for errors. Defaults to an empty response with the
+    /// error's status.
+    error_handler: Option<ErrorHandler>,
+    /// Whether `100 Continue` is sent on the first read of the body.
+    /// Defaults to true.
+    auto_continue: bool,
+    /// Whether panics in the endpoint are caught. Defaults to false.
+    catch_panics: bool,
+    /// Reports panics caught in the endpoint. Defaults to logging them.
+    panic_handler: Option<PanicHandler>,
+    /// Maximum length of a request head in bytes. Defaults to 8KiB.
+    max_head_length: usize,
+    /// Maximum number of headers in a request head. Defaults to 128.
+    max_headers: usize,
+    /// How strictly request heads and trailers are parsed. Defaults to
+    /// strict.
+    parse_mode: ParseMode,
+    /// Information about the connection. Defaults to none.
+    connection_info: Option<ConnectionInfo>,
+    /// The host of HTTP/1.0 requests without a Host header. Defaults to
+    /// `localhost`.
+    default_host: String,
+    /// Whether connections start with a PROXY protocol header. Defaults to
+    /// false.
+    proxy_protocol: bool,
+    /// Maximum size of the data in each chunk of a chunked response body.
+    /// Defaults to 8KiB.
+    chunk_size: usize,
+    /// Whether body reads that are ready at once are sent as one chunk.
+    /// Defaults to false.
+    chunk_coalescing: bool,
+}
+
+impl ServerOptions {
+    /// Create a new instance with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the timeout to receive a request head, or `None` to wait forever.
+    ///
+    /// On the first request of a connection this also covers waiting for the
+    /// request to start. Connections that time out are closed.
+    pub fn with_headers_timeout(mut self, headers_timeout: Option<Duration>) -> Self {
+        self.headers_timeout = headers_timeout;
+        self
+    }
+
+    /// Set how long a kept-alive connection may stay idle waiting for the
+    /// next request before it is closed.
+    ///
+    /// The timeout is advertised to clients in the `Keep-Alive` header, in
+    /// whole seconds. Timeouts under a second aren't advertised.
+    pub fn with_keep_alive_timeout(mut self, keep_alive_timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(keep_alive_timeout);
+        self
+    }
+
+    /// Set the maximum number of requests served on a single connection.
+    ///
+    /// The response to the last request carries `Connection: close`, and the
+    /// number of remaining requests is advertised in the `Keep-Alive` header.
+    pub fn with_max_requests(mut self, max_requests: usize) -> Self {
+        self.max_requests = Some(max_requests);
+        self
+    }
+
+    /// Set how long reading the request body may go without making progress.
+    ///
+    /// When the timeout is hit, reading the body fails with
+    /// [`Error::BodyTimeout`] and the connection
+    /// is closed.
+    pub fn with_body_timeout(mut self, body_timeout: Duration) -> Self {
+        self.body_timeout = Some(body_timeout);
+        self
+    }
+
+    /// Set how long writing the response may go without making progress.
+    ///
+    /// When the timeout is hit, the connection is closed with
+    /// [`Error::WriteTimeout`].
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = Some(write_timeout);
+        self
+    }
+
+    /// Set the maximum length of a request body in bytes.
+    ///
+    /// Requests declaring a longer `Content-Length` are rejected with
+    /// [`Error::BodyTooLarge`] before reaching
+    /// the endpoint. Reading a longer chunked body fails with the same error
+    /// once the limit is crossed, and the connection is closed afterwards.
+    pub fn with_max_body_length(mut self, max_body_length: u64) -> Self {
+        self.max_body_length = Some(max_body_length);
+        self
+    }
+
+    /// Set the maximum number of unread request body bytes the server will
+    /// discard after a response in order to keep the connection alive.
+    ///
+    /// If more than this is left, the response is sent with
+    /// `Connection: close` where possible, and the connection is closed
+    /// instead of reading on.
+    pub fn with_max_drain_length(mut self, max_drain_length: u64) -> Self {
+        self.max_drain_length = Some(max_drain_length);
+        self
+    }
+
+    /// Set a signal to gracefully shut down the connection.
+    ///
+    /// See [`Shutdown`] for details.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Set the maximum number of connections [`serve`] handles at once.
+    ///
+    /// Once reached, no new connections are accepted until one finishes. A
+    /// maximum of 0 means no limit.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections).filter(|&max| max > 0);
+        self
+    }
+
+    /// Set a function building the response sent for an error.
+    ///
+    /// This is used when the endpoint returns an error, and when a request
+    /// can't be decoded. By default an empty response with the error's
+    /// status is sent.
+    pub fn with_error_handler<H>(mut self, handler: H) -> Self
+    where
+        H: Fn(&http_types::Error) -> Response + Send + Sync + 'static,
+    {
+        self.error_handler = Some(ErrorHandler(Arc::new(handler)));
+        self
+    }
+
+    /// Set whether `100 Continue` is sent the first time the body of a
+    /// request with `Expect: 100-continue` is read.
+    ///
+    /// When disabled, endpoints send it explicitly through the request's
+    /// [`ExpectContinue`] extension. Reading the body waits until they do,
+    /// since the client holds the body back until then. Once the response
+    /// has started, reads go ahead without it.
+    pub fn with_auto_continue(mut self, auto_continue: bool) -> Self {
+        self.auto_continue = auto_continue;
+        self
+    }
+
+    /// Set whether panics in the endpoint are caught.
+    ///
+    /// A caught panic is answered with a `500 Internal Server Error` and
+    /// `Connection: close`, and reported to the panic handler.
+    pub fn with_catch_panics(mut self, catch_panics: bool) -> Self {
+        self.catch_panics = catch_panics;
+        self
+    }
+
+    /// Set a function that is told about panics caught in the endpoint, and
+    /// start catching them.
+    ///
+    /// The function receives the panic payload. By default the panic message
+    /// is logged.
+    pub fn with_panic_handler<H>(mut self, handler: H) -> Self
+    where
+        H: Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        self.catch_panics = true;
+        self.panic_handler = Some(PanicHandler(Arc::new(handler)));
+        self
+    }
+
+    /// Set the maximum length of a request head in bytes.
+    ///
+    /// Requests with a longer head are rejected with
+    /// [`Error::HeadTooLarge`].
+    pub fn with_max_head_length(mut self, max_head_length: usize) -> Self {
+        self.max_head_length = max_head_length;
+        self
+    }
+
+    /// Set the maximum number of headers in a request head.
+    ///
+    /// Requests with more headers are rejected with
+    /// [`Error::TooManyHeaders`].
+    pub fn with_max_headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
+
+    /// Set how strictly request heads and the trailers of chunked request
+    /// bodies are parsed.
+    ///
+    /// Requests that don't parse are rejected with `400 Bad Request`.
+    pub fn with_parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = parse_mode;
+        self
+    }
+
+    /// Set information about the connection, to be passed on to requests.
+    ///
+    /// This is specific to a single connection. [`serve`] sets the addresses
+    /// of each connection it accepts.
+    pub fn with_connection_info(mut self, connection_info: ConnectionInfo) -> Self {
+        self.connection_info = Some(connection_info);
+        self
+    }
+
+    /// Set the host used for the URL of HTTP/1.0 requests that don't send a
+    /// Host header, as `host[:port]`.
+    ///
+    /// HTTP/1.1 requests must send a Host header and are rejected without
+    /// one. Defaults to `localhost`.
+    pub fn with_default_host(mut self, default_host: impl Into<String>) -> Self {
+        self.default_host = default_host.into();
+        self
+    }
+
+    /// Set whether connections start with a PROXY protocol header, as sent by
+    /// load balancers such as HAProxy and AWS NLB.
+    ///
+    /// Both version 1 and 2 are supported. The header is read before the
+    /// first request, and the client and destination addresses it carries
+    /// become the peer and local addresses of every request on the
+    /// connection. Connections without a valid header are closed.
+    pub fn with_proxy_protocol(mut self, proxy_protocol: bool) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    /// Set the maximum size of the data in each chunk of a chunked response
+    /// body.
+    ///
+    /// Sizes are clamped to between 1 byte and 16 MiB.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Set whether response body reads that complete immediately one after
+    /// another are combined into a single chunk, up to the chunk size.
+    ///
+    /// This cuts the framing overhead of bodies that stream many small
+    /// pieces. A chunk is still sent as soon as the body has no more data
+    /// ready.
+    pub fn with_chunk_coalescing(mut self, chunk_coalescing: bool) -> Self {
+        self.chunk_coalescing = chunk_coalescing;
+        self
+    }
+
+    /// The timeout to receive a request head.
+    pub fn headers_timeout(&self) -> Option<Duration> {
+        self.headers_timeout
+    }
+
+    /// The timeout to wait for a subsequent request on a kept-alive connection.
+    pub fn keep_alive_timeout(&self) -> Option<Duration> {
+        self.keep_alive_timeout
+    }
+
+    /// The maximum number of requests served on a single connection.
+    pub fn max_requests(&self) -> Option<usize> {
+        self.max_requests
+    }
+
+    /// The timeout for the request body to make progress.
+    pub fn body_timeout(&self) -> Option<Duration> {
+        self.body_timeout
+    }
+
+    /// The timeout for the response write to make progress.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// The maximum length of a request body in bytes.
+    pub fn max_body_length(&self) -> Option<u64> {
+        self.max_body_length
+    }
+
+    /// The maximum number of unread request body bytes discarded after a
+    /// response.
+    pub fn max_drain_length(&self) -> Option<u64> {
+        self.max_drain_length
+    }
+
+    /// The signal to gracefully shut down the connection.
+    pub fn shutdown(&self) -> Option<&Shutdown> {
+        self.shutdown.as_ref()
+    }
+
+    /// The maximum number of connections [`serve`] handles at once.
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    /// Whether `100 Continue` is sent on the first read of the body.
+    pub fn auto_continue(&self) -> bool {
+        self.auto_continue
+    }
+
+    /// Whether panics in the endpoint are caught.
+    pub fn catch_panics(&self) -> bool {
+        self.catch_panics
+    }
+
+    /// How strictly request heads and trailers are parsed.
+    pub fn parse_mode(&self) -> ParseMode {
+        self.parse_mode
+    }
+
+    /// Information about the connection.
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.connection_info.as_ref()
+    }
+
+    /// The host of HTTP/1.0 requests without a Host header.
+    pub fn default_host(&self) -> &str {
+        &self.default_host
+    }
+
+    /// Whether connections start with a PROXY protocol header.
+    pub fn proxy_protocol(&self) -> bool {
+        self.proxy_protocol
+    }
+
+    /// The maximum size of the data in each chunk of a chunked response body.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Whether response body reads that are ready at once are sent as one
+    /// chunk.
+    pub fn chunk_coalescing(&self) -> bool {
+        self.chunk_coalescing
+    }
+
+    /// The maximum length of a request head in bytes.
+    pub fn max_head_length(&self) -> usize {
+        self.max_head_length
+    }
+
+    /// The maximum number of headers in a request head.
+    pub fn max_headers(&self) -> usize {
+        self.max_headers
+    }
+
+    /// Create a parser for request heads with these limits.
+    pub(crate) fn head_parser(&self) -> HeadParser {
+        HeadParser::request(self.max_head_length, self.max_headers, self.parse_mode)
+    }
 }
 
 impl Default for ServerOptions {
     fn default() -> Self {
         Self {
             headers_timeout: Some(Duration::from_secs(60)),
+            keep_alive_timeout: None,
+            max_requests: None,
+            body_timeout: None,
+            write_timeout: None,
+            max_body_length: None,
+            max_drain_length: None,
+            shutdown: None,
+            max_connections: None,
+            error_handler: None,
+            auto_continue: true,
+            catch_panics: false,
+            panic_handler: None,
+            max_head_length: MAX_HEAD_LENGTH,
+            max_headers: MAX_HEADERS,
+            parse_mode: ParseMode::Strict,
+            connection_info: None,
+            default_host: DEFAULT_HOST.to_owned(),
+            proxy_protocol: false,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            chunk_coalescing: false,
         }
     }
 }
 
 /// Accept a new incoming HTTP/1.1 connection.
 ///
-/// Supports `KeepAlive` requests by default.
+/// Supports `KeepAlive` requests by default. HTTP/1.0 requests are also
+/// accepted, and are closed after the response unless they opt into
+/// `Connection: keep-alive`. Errors returned by the endpoint are sent to the
+/// client as a response with the error's status.
 pub async fn accept<RW, F, Fut>(io: RW, endpoint: F) -> http_types::Result<()>
 where
     RW: Read + Write + Clone + Send + Sync + Unpin + 'static,
@@ -60,8 +506,12 @@ where
 #[derive(Debug)]
 pub struct Server<RW, F, Fut> {
     io: RW,
+    reader: ReadBuffer<RW>,
+    parser: HeadParser,
     endpoint: F,
     opts: ServerOptions,
+    requests: usize,
+    proxy_header_read: bool,
     _phantom: PhantomData<Fut>,
 }
 
@@ -83,16 +533,22 @@ where
 {
     /// builds a new server
     pub fn new(io: RW, endpoint: F) -> Self {
+        let opts = ServerOptions::default();
         Self {
+            reader: ReadBuffer::new(io.clone()),
+            parser: opts.head_parser(),
             io,
             endpoint,
-            opts: Default::default(),
+            opts,
+            requests: 0,
+            proxy_header_read: false,
             _phantom: PhantomData,
         }
     }
 
     /// with opts
     pub fn with_opts(mut self, opts: ServerOptions) -> Self {
+        self.parser = opts.head_parser();
         self.opts = opts;
         self
     }
@@ -110,19 +566,46 @@ where
         F: Fn(Request) -> Fut,
         Fut: Future<Output = http_types::Result<Response>>,
     {
-        // Decode a new request, timing out if this takes longer than the timeout duration.
-        let fut = decode(self.io.clone());
-
-        let (req, mut body) = if let Some(timeout_duration) = self.opts.headers_timeout {
-            match timeout(timeout_duration, fut).await {
-                Ok(Ok(Some(r))) => r,
-                Ok(Ok(None)) | Err(TimeoutError { .. }) => return Ok(ConnectionStatus::Close), /* EOF or timeout */
-                Ok(Err(e)) => return Err(e),
+        if self.opts.proxy_protocol && !self.proxy_header_read {
+            self.proxy_header_read = true;
+            if !self.read_proxy_header().await? {
+                return Ok(ConnectionStatus::Close);
             }
+        }
+
+        // Decode a new request, timing out if this takes longer than the timeout duration.
+        // Kept-alive connections may also time out while waiting for the request to start.
+        let idle_timeout = if self.requests > 0 {
+            self.opts.keep_alive_timeout
         } else {
-            match fut.await? {
-                Some(r) => r,
-                None => return Ok(ConnectionStatus::Close), /* EOF */
+            None
+        };
+        let mut version = None;
+        let fut = decode_with_idle_timeout(
+            self.io.clone(),
+            &mut self.reader,
+            &mut self.parser,
+            &self.opts,
+            idle_timeout,
+            &mut version,
+        );
+
+        // Stop waiting for a request as soon as we're asked to shut down.
+        let decoded = match &self.opts.shutdown {
+            Some(shutdown) if shutdown.is_triggered() => return Ok(ConnectionStatus::Close),
+            Some(shutdown) => match shutdown.race(fut).await {
+                Some(decoded) => decoded,
+                None => return Ok(ConnectionStatus::Close), /* shutdown */
+            },
+            None => fut.await,
+        };
+
+        let (req, body) = match decoded {
+            Ok(Some(r)) => r,
+            Ok(None) => return Ok(ConnectionStatus::Close), /* EOF */
+            Err(e) => {
+                self.write_decode_error(&e, version).await;
+                return Err(e);
             }
         };
 
@@ -135,20 +618,141 @@ where
         let connection_header_is_upgrade = connection_header_as_str
             .split(',')
             .any(|s| s.trim().eq_ignore_ascii_case("upgrade"));
-        let mut close_connection = connection_header_as_str.eq_ignore_ascii_case("close");
+
+        // HTTP/1.0 connections close after each response unless the client
+        // explicitly asks for them to be kept alive.
+        let version = req.version();
+        let http_1_0 = version == Some(Version::Http1_0);
+        let mut close_connection = if http_1_0 {
+            !connection_header_as_str
+                .split(',')
+                .any(|s| s.trim().eq_ignore_ascii_case("keep-alive"))
+        } else {
+            connection_header_as_str.eq_ignore_ascii_case("close")
+        };
 
         let upgrade_requested = has_upgrade_header && connection_header_is_upgrade;
 
         let method = req.method();
+        let expect = req.ext().get::<ExpectContinue>().cloned();
+
+        // Trailers are only sent to clients that say they accept them.
+        //
+        // https://tools.ietf.org/html/rfc7230#section-4.3
+        let accepts_trailers = req
+            .header(TE)
+            .map(|te| {
+                te.iter().any(|te| {
+                    te.as_str()
+                        .split(',')
+                        .any(|s| s.trim().eq_ignore_ascii_case("trailers"))
+                })
+            })
+            .unwrap_or(false);
 
         // Pass the request to the endpoint and encode the response.
-        let mut res = (self.endpoint)(req).await?;
+        let endpoint = &self.endpoint;
+        let result = if self.opts.catch_panics {
+            catch_panic(async move { endpoint(req).await }).await
+        } else {
+            Ok(endpoint(req).await)
+        };
+
+        // A panic may have left the request body in any state, so the
+        // connection can't be reused.
+        let panicked = result.is_err();
+        let result = result.unwrap_or_else(|payload| {
+            self.report_panic(&*payload);
+            Err(http_types::Error::from_str(
+                StatusCode::InternalServerError,
+                "endpoint panicked",
+            ))
+        });
+
+        let mut res = match result {
+            Ok(res) => res,
+            Err(e) => {
+                let e = Error::recover_status(e);
+                if e.status().is_server_error() {
+                    log::error!("endpoint failed: {}", e);
+                } else {
+                    log::debug!("endpoint failed: {}", e);
+                }
+                self.error_response(&e)
+            }
+        };
+        res.set_version(version);
+
+        // Write any `100 Continue` the endpoint asked for ahead of the
+        // response, and make sure it isn't sent after it.
+        if let Some(expect) = &expect {
+            expect.finish().await.map_err(Error::from_io)?;
+        }
+
+        if panicked {
+            close_connection = true;
+            res.insert_header(CONNECTION, "close");
+        }
 
         close_connection |= res
             .header(CONNECTION)
             .map(|c| c.as_str().eq_ignore_ascii_case("close"))
             .unwrap_or(false);
 
+        // HTTP/1.0 has no chunked encoding, so a streaming body can only be
+        // delimited by closing the connection.
+        if http_1_0 {
+            close_connection |= res.len().is_none();
+        }
+
+        // Finish the in-flight request, but don't accept another one.
+        let shutting_down = self
+            .opts
+            .shutdown
+            .as_ref()
+            .map(|shutdown| shutdown.is_triggered())
+            .unwrap_or(false);
+        if shutting_down && !close_connection {
+            close_connection = true;
+            res.insert_header(CONNECTION, "close");
+        }
+
+        // Don't bother reading a body we already know is too large to drain,
+        // one that has stalled, or one the client was never told to send.
+        let skip_drain = body.is_too_large()
+            || body.timed_out()
+            || match (self.opts.max_drain_length, body.remaining()) {
+                (Some(max), Some(remaining)) => remaining > max,
+                _ => false,
+            }
+            || (expect.map(|expect| !expect.is_sent()).unwrap_or(false)
+                && body.remaining() != Some(0));
+        if skip_drain && !close_connection {
+            close_connection = true;
+            res.insert_header(CONNECTION, "close");
+        }
+
+        self.requests += 1;
+        let remaining_requests = self
+            .opts
+            .max_requests
+            .map(|max| max.saturating_sub(self.requests));
+        if remaining_requests == Some(0) && !close_connection {
+            close_connection = true;
+            res.insert_header(CONNECTION, "close");
+        }
+
+        if !close_connection {
+            if http_1_0 {
+                res.insert_header(CONNECTION, "keep-alive");
+            }
+            if res.header(KEEP_ALIVE).is_none() {
+                if let Some(keep_alive) = self.keep_alive_header(remaining_requests) {
+                    res.insert_header(KEEP_ALIVE, keep_alive);
+                }
+            }
+        }
+
         let upgrade_provided = res.status() == StatusCode::SwitchingProtocols && res.has_upgrade();
 
         let upgrade_sender = if upgrade_requested && upgrade_provided {
@@ -157,16 +761,55 @@ where
             None
         };
 
-        let mut encoder = Encoder::new(res, method);
+        let mut encoder = Encoder::new(res, method)
+            .with_trailers(accepts_trailers)
+            .with_chunk_size(self.opts.chunk_size)
+            .with_chunk_coalescing(self.opts.chunk_coalescing);
 
-        let bytes_written = io::copy(&mut encoder, &mut self.io).await?;
+        let bytes_written = self.write(&mut encoder).await?;
         log::trace!("wrote {} response bytes", bytes_written);
 
-        let body_bytes_discarded = io::copy(&mut body, &mut io::sink()).await?;
-        log::trace!(
-            "discarded {} unread request body bytes",
-            body_bytes_discarded
-        );
+        // The request body hands the connection's read buffer back once it's
+        // dropped, which the response may have delayed. An endpoint that still
+        // holds on to it may read from the connection, so it can't be reused.
+        drop(encoder);
+        let reader = match body.take_reader() {
+            Ok(reader) => reader,
+            Err(()) => {
+                log::trace!("request body still in use, closing connection");
+                close_connection = true;
+                None
+            }
+        };
+
+        if let (false, Some(mut reader)) = (skip_drain, reader) {
+            let max_drain_length = self.opts.max_drain_length.unwrap_or(u64::MAX);
+            let mut drain = (&mut reader).take(max_drain_length.saturating_add(1));
+            let body_bytes_discarded = match io::copy(&mut drain, &mut io::sink()).await {
+                Ok(discarded) => discarded,
+                Err(_) if reader.get_ref().is_too_large() => {
+                    log::trace!("unread request body exceeds maximum length, closing connection");
+                    close_connection = true;
+                    0
+                }
+                Err(e) => return Err(Error::from_io(e)),
+            };
+            log::trace!(
+                "discarded {} unread request body bytes",
+                body_bytes_discarded
+            );
+
+            // A chunked body's length isn't known up front, so we only find
+            // out it's too large once we've read past the limit.
+            if body_bytes_discarded > max_drain_length {
+                log::trace!("unread request body exceeds drain limit, closing connection");
+                close_connection = true;
+            }
+
+            // Take back the read buffer, with whatever the client sent after
+            // the body.
+            self.reader = reader.into_inner().into_reader();
+        }
 
         if let Some(upgrade_sender) = upgrade_sender {
             upgrade_sender.send(Connection::new(self.io.clone())).await;
@@ -177,4 +820,98 @@ where
             Ok(ConnectionStatus::KeepAlive)
         }
     }
+
+    /// Write an encoded message to the connection, subject to the write timeout.
+    async fn write(&mut self, encoder: &mut Encoder) -> http_types::Result<u64> {
+        let timeout = self.opts.write_timeout;
+        let error = Error::WriteTimeout(timeout.unwrap_or_default());
+        let mut io = IdleTimeout::new(&mut self.io, timeout, error);
+        encoder.write_to(&mut io).await.map_err(Error::from_io)
+    }
+
+    /// The value of the `Keep-Alive` header advertising the connection's
+    /// limits, if any are configured.
+    fn keep_alive_header(&self, remaining_requests: Option<usize>) -> Option<String> {
+        // The header counts whole seconds. Rounding a sub-second timeout down
+        // to 0 would tell clients not to reuse the connection, and rounding it
+        // up would promise more than we wait, so it's left out instead.
+        let timeout = self
+            .opts
+            .keep_alive_timeout
+            .filter(|timeout| timeout.as_secs() > 0)
+            .map(|timeout| format!("timeout={}", timeout.as_secs()));
+        let max = remaining_requests.map(|max| format!("max={}", max));
+        match (timeout, max) {
+            (Some(timeout), Some(max)) => Some(format!("{}, {}", timeout, max)),
+            (timeout, max) => timeout.or(max),
+        }
+    }
+
+    /// Read the PROXY protocol header, and record the addresses it carries.
+    ///
+    /// Returns false if the header isn't received within the headers timeout.
+    async fn read_proxy_header(&mut self) -> http_types::Result<bool>
+    where
+        RW: Read + Unpin,
+    {
+        let header = match self.opts.headers_timeout {
+            Some(duration) => {
+                match async_std::future::timeout(duration, read_proxy_header(&mut self.reader))
+                    .await
+                {
+                    Ok(header) => header?,
+                    Err(_) => return Ok(false),
+                }
+            }
+            None => read_proxy_header(&mut self.reader).await?,
+        };
+
+        if let Some(header) = header {
+            let info = self.opts.connection_info.take().unwrap_or_default();
+            let info = info
+                .with_peer_addr(header.source)
+                .with_local_addr(header.destination);
+            self.opts.connection_info = Some(info);
+        }
+        Ok(true)
+    }
+
+    /// Report a panic caught in the endpoint to the panic handler.
+    fn report_panic(&self, payload: &PanicPayload) {
+        match &self.opts.panic_handler {
+            Some(PanicHandler(handler)) => handler(payload),
+            None => log::error!(
+                "endpoint panicked: {}",
+                panic_message(payload).unwrap_or("Box<dyn Any>")
+            ),
+        }
+    }
+
+    /// Build the response sent to the client for an error.
+    fn error_response(&self, err: &http_types::Error) -> Response {
+        match &self.opts.error_handler {
+            Some(ErrorHandler(handler)) => handler(err),
+            None => Response::new(err.status()),
+        }
+    }
+
+    /// Respond to a request that failed to decode with the error's status
+    /// code, so the client isn't left with a reset connection.
+    ///
+    /// The response uses the request's HTTP version if it was parsed.
+    async fn write_decode_error(&mut self, err: &http_types::Error, version: Option<Version>) {
+        // There's no point in responding if the connection itself failed.
+        if err.downcast_ref::<io::Error>().is_some() {
+            return;
+        }
+
+        let mut res = self.error_response(err);
+        res.insert_header(CONNECTION, "close");
+        res.set_version(version);
+
+        let mut encoder = Encoder::new(res, Method::Get);
+        if let Err(e) = self.write(&mut encoder).await {
+            log::debug!("failed to write decode error response: {}", e);
+        }
+    }
 }
@@ -0,0 +1,299 @@
+//! Parse the PROXY protocol header sent by load balancers.
+//!
+//! https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use async_std::future;
+use async_std::io::{self, Read};
+use http_types::{bail_status, ensure_status, format_err_status};
+
+use crate::read_buffer::ReadBuffer;
+
+/// The start of a version 1 header.
+const V1_PREFIX: &[u8] = b"PROXY ";
+
+/// The maximum length of a version 1 header, including the CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// The signature that starts a version 2 header.
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The length of a version 2 header before its addresses.
+const V2_HEADER_LENGTH: usize = 16;
+
+/// The addresses of the connection between the client and the proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ProxyHeader {
+    /// The address of the client.
+    pub(crate) source: SocketAddr,
+    /// The address the client connected to.
+    pub(crate) destination: SocketAddr,
+}
+
+/// Read a version 1 or 2 PROXY protocol header from the start of a connection.
+///
+/// Returns `None` if the proxy doesn't know the addresses, or is connecting
+/// on its own behalf. The header is parsed out of the connection's buffer and
+/// exactly its length is consumed, so that whatever follows is left for the
+/// request decoder.
+pub(crate) async fn read_proxy_header<R>(
+    reader: &mut ReadBuffer<R>,
+) -> http_types::Result<Option<ProxyHeader>>
+where
+    R: Read + Unpin,
+{
+    let limit = V2_HEADER_LENGTH + u16::MAX as usize;
+    let mut parsed = false;
+    let mut parse = parse_proxy_header;
+    let header = future::poll_fn(|cx| reader.poll_head(cx, limit, &mut parsed, &mut parse));
+    match header.await? {
+        Some(header) => Ok(header),
+        None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+    }
+}
+
+/// Parse a PROXY protocol header at the start of `buf`.
+///
+/// Returns `None` while the header is incomplete, or the header and its
+/// length.
+fn parse_proxy_header(buf: &[u8]) -> http_types::Result<Option<(Option<ProxyHeader>, usize)>> {
+    let starts_with = |prefix: &[u8]| {
+        let len = std::cmp::min(buf.len(), prefix.len());
+        buf[..len] == prefix[..len]
+    };
+    if starts_with(V1_PREFIX) {
+        if buf.len() < V1_PREFIX.len() {
+            return Ok(None);
+        }
+        parse_v1(buf)
+    } else if starts_with(V2_SIGNATURE) {
+        if buf.len() < V2_HEADER_LENGTH {
+            return Ok(None);
+        }
+        parse_v2(buf)
+    } else {
+        bail_status!(400, "Missing PROXY protocol header")
+    }
+}
+
+/// Parse a version 1 header, up to and including the CRLF.
+fn parse_v1(buf: &[u8]) -> http_types::Result<Option<(Option<ProxyHeader>, usize)>> {
+    let searched = &buf[..std::cmp::min(buf.len(), V1_MAX_LENGTH)];
+    let len = match searched.windows(2).position(|window| window == b"\r\n") {
+        Some(i) => i + 2,
+        None => {
+            ensure_status!(
+                buf.len() < V1_MAX_LENGTH,
+                400,
+                "PROXY protocol header too long"
+            );
+            return Ok(None);
+        }
+    };
+    let line = &buf[..len];
+
+    let line = std::str::from_utf8(&line[..len - 2])
+        .map_err(|_| format_err_status!(400, "Invalid PROXY protocol header"))?;
+    let parts = line.split(' ').skip(1).collect::<Vec<_>>();
+    let header = match parts.as_slice() {
+        ["UNKNOWN", ..] => None,
+        [protocol @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] => {
+            let parse_ip = |ip: &str| -> http_types::Result<IpAddr> {
+                let ip = if *protocol == "TCP4" {
+                    Ipv4Addr::from_str(ip).map(IpAddr::V4)
+                } else {
+                    Ipv6Addr::from_str(ip).map(IpAddr::V6)
+                };
+                ip.map_err(|_| format_err_status!(400, "Invalid PROXY protocol address"))
+            };
+            let parse_port = |port: &str| -> http_types::Result<u16> {
+                ensure_status!(
+                    !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()),
+                    400,
+                    "Invalid PROXY protocol port"
+                );
+                port.parse()
+                    .map_err(|_| format_err_status!(400, "Invalid PROXY protocol port"))
+            };
+            Some(ProxyHeader {
+                source: SocketAddr::new(parse_ip(source)?, parse_port(source_port)?),
+                destination: SocketAddr::new(parse_ip(destination)?, parse_port(destination_port)?),
+            })
+        }
+        _ => bail_status!(400, "Invalid PROXY protocol header"),
+    };
+    Ok(Some((header, len)))
+}
+
+/// Parse a version 2 header, including its addresses and TLVs.
+fn parse_v2(buf: &[u8]) -> http_types::Result<Option<(Option<ProxyHeader>, usize)>> {
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0F;
+    let family = buf[13] >> 4;
+    let transport = buf[13] & 0x0F;
+    let length = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    ensure_status!(version == 2, 400, "Unsupported PROXY protocol version");
+
+    let len = V2_HEADER_LENGTH + length;
+    if buf.len() < len {
+        return Ok(None);
+    }
+    let addresses = &buf[V2_HEADER_LENGTH..len];
+
+    match command {
+        // The proxy connects on its own behalf, e.g. for health checks.
+        0x0 => return Ok(Some((None, len))),
+        0x1 => {}
+        _ => bail_status!(400, "Unsupported PROXY protocol command"),
+    }
+
+    // Only TCP connections carry HTTP. An unspecified family and transport
+    // means the proxy doesn't know the addresses.
+    match (family, transport) {
+        (0x0, 0x0) => return Ok(Some((None, len))),
+        (_, 0x1) => {}
+        _ => bail_status!(400, "Unsupported PROXY protocol transport"),
+    }
+
+    let (source, destination) = match family {
+        // TCP over IPv4.
+        0x1 => {
+            ensure_status!(length >= 12, 400, "PROXY protocol header too short");
+            let ip = |i: usize| {
+                let mut octets = [0; 4];
+                octets.copy_from_slice(&addresses[i..i + 4]);
+                IpAddr::from(octets)
+            };
+            (ip(0), ip(4))
+        }
+        // TCP over IPv6.
+        0x2 => {
+            ensure_status!(length >= 36, 400, "PROXY protocol header too short");
+            let ip = |i: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&addresses[i..i + 16]);
+                IpAddr::from(octets)
+            };
+            (ip(0), ip(16))
+        }
+        // Unix sockets, or an unspecified family.
+        _ => return Ok(Some((None, len))),
+    };
+    let ports = if family == 0x1 {
+        &addresses[8..12]
+    } else {
+        &addresses[32..36]
+    };
+    let header = ProxyHeader {
+        source: SocketAddr::new(source, u16::from_be_bytes([ports[0], ports[1]])),
+        destination: SocketAddr::new(destination, u16::from_be_bytes([ports[2], ports[3]])),
+    };
+    Ok(Some((Some(header), len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::io::{Cursor, ReadExt};
+    use async_std::task::{self, Context, Poll};
+    use std::pin::Pin;
+
+    /// A reader that returns a single byte at a time.
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let len = std::cmp::min(buf.len(), 1);
+            Pin::new(&mut self.0).poll_read(cx, &mut buf[..len])
+        }
+    }
+
+    fn read(header: &[u8]) -> http_types::Result<Option<ProxyHeader>> {
+        let trickled = task::block_on(read_from(Trickle(Cursor::new(header.to_vec()))));
+        let buffered = task::block_on(read_from(Cursor::new(header.to_vec())));
+        match (&trickled, &buffered) {
+            (Ok(trickled), Ok(buffered)) => assert_eq!(trickled, buffered),
+            (Err(_), Err(_)) => {}
+            _ => panic!("reading in pieces gave a different result"),
+        }
+        buffered
+    }
+
+    async fn read_from(io: impl Read + Unpin) -> http_types::Result<Option<ProxyHeader>> {
+        let mut reader = ReadBuffer::new(io);
+        let parsed = read_proxy_header(&mut reader).await?;
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await?;
+        assert_eq!(rest, b"GET");
+        Ok(parsed)
+    }
+
+    #[test]
+    fn v1() {
+        let header = read(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET").unwrap();
+        assert_eq!(
+            header,
+            Some(ProxyHeader {
+                source: "192.168.0.1:56324".parse().unwrap(),
+                destination: "192.168.0.11:443".parse().unwrap(),
+            })
+        );
+
+        let header = read(b"PROXY TCP6 ::1 2001:db8::1 56324 443\r\nGET").unwrap();
+        assert_eq!(header.unwrap().source, "[::1]:56324".parse().unwrap());
+
+        assert_eq!(read(b"PROXY UNKNOWN\r\nGET").unwrap(), None);
+        assert!(read(b"PROXY TCP4 192.168.0.1 ::1 56324 443\r\nGET").is_err());
+        assert!(read(b"PROXY TCP4 192.168.0.1 192.168.0.11 +1 443\r\nGET").is_err());
+
+        let mut long = b"PROXY UNKNOWN ".to_vec();
+        long.resize(V1_MAX_LENGTH, b'a');
+        long.extend_from_slice(b"\r\nGET");
+        assert!(read(&long).is_err());
+    }
+
+    #[test]
+    fn v2() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 15]);
+        header.extend_from_slice(&[192, 168, 0, 1, 192, 168, 0, 11, 0xDC, 0x04, 0x01, 0xBB]);
+        header.extend_from_slice(&[0x04, 0, 0]); // An empty NOOP TLV.
+        header.extend_from_slice(b"GET");
+        assert_eq!(
+            read(&header).unwrap(),
+            Some(ProxyHeader {
+                source: "192.168.0.1:56324".parse().unwrap(),
+                destination: "192.168.0.11:443".parse().unwrap(),
+            })
+        );
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        local.extend_from_slice(b"GET");
+        assert_eq!(read(&local).unwrap(), None);
+
+        // UDP over IPv4, and an unknown transport.
+        for family_transport in &[0x12, 0x13] {
+            let mut header = V2_SIGNATURE.to_vec();
+            header.extend_from_slice(&[0x21, *family_transport, 0, 12]);
+            header.extend_from_slice(&[192, 168, 0, 1, 192, 168, 0, 11, 0xDC, 0x04, 0x01, 0xBB]);
+            header.extend_from_slice(b"GET");
+            assert!(read(&header).is_err());
+        }
+    }
+
+    #[test]
+    fn missing_header() {
+        task::block_on(async {
+            let mut reader = ReadBuffer::new(Cursor::new(b"GET / HTTP/1.1\r\n".to_vec()));
+            let err = read_proxy_header(&mut reader).await.unwrap_err();
+            assert_eq!(err.status(), 400);
+        })
+    }
+}
@@ -0,0 +1,132 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::io;
+use async_std::net::TcpListener;
+use async_std::task;
+use http_types::{Request, Response};
+
+use super::{accept_with_opts, ConnectionInfo, ServerOptions, Shutdown};
+
+/// How long to wait before accepting again after an accept error.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serve HTTP/1.1 connections accepted from a listener.
+///
+/// Each connection is handled on its own task with [`accept_with_opts`].
+/// If [`ServerOptions::with_max_connections`] is set, no new connections are
+/// accepted while that many are active. Requests carry the peer and local
+/// addresses of their connection. Errors on individual connections are
+/// logged rather than returned.
+///
+/// If [`ServerOptions::with_shutdown`] is set, triggering the signal stops
+/// accepting new connections, and this returns once all active connections
+/// have finished.
+///
+/// # Example
+///
+/// ```no_run
+/// use async_std::net::TcpListener;
+/// use http_types::{Response, StatusCode};
+///
+/// #[async_std::main]
+/// async fn main() -> http_types::Result<()> {
+///     let listener = TcpListener::bind(("127.0.0.1", 8080)).await?;
+///     let opts = async_h1::ServerOptions::new().with_max_connections(1024);
+///     async_h1::server::serve(
+///         listener,
+///         |_req| async move {
+///             let mut res = Response::new(StatusCode::Ok);
+///             res.set_body("Hello");
+///             Ok(res)
+///         },
+///         opts,
+///     )
+///     .await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn serve<F, Fut>(
+    listener: TcpListener,
+    endpoint: F,
+    opts: ServerOptions,
+) -> io::Result<()>
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = http_types::Result<Response>> + Send + 'static,
+{
+    let endpoint = Arc::new(endpoint);
+    let shutdown = opts.shutdown.clone();
+
+    // Each active connection holds a permit, so sending blocks once the
+    // maximum number of connections is reached.
+    let permits = opts.max_connections.map(async_channel::bounded::<()>);
+
+    // Each connection task holds a sender, so the receiver only errors once
+    // they have all finished.
+    let (active, finished) = async_channel::bounded::<()>(1);
+
+    loop {
+        if let Some((sender, _)) = &permits {
+            match until_shutdown(&shutdown, sender.send(())).await {
+                Some(_) => {}
+                None => break,
+            }
+        }
+
+        let stream = match until_shutdown(&shutdown, listener.accept()).await {
+            Some(Ok((stream, _))) => stream,
+            Some(Err(e)) => {
+                log::error!("failed to accept connection: {}", e);
+                if let Some((_, receiver)) = &permits {
+                    let _ = receiver.try_recv();
+                }
+                // Errors such as running out of file descriptors tend to
+                // persist, so don't retry right away.
+                match until_shutdown(&shutdown, task::sleep(ACCEPT_ERROR_BACKOFF)).await {
+                    Some(_) => continue,
+                    None => break,
+                }
+            }
+            None => break,
+        };
+
+        let mut info = ConnectionInfo::new();
+        if let Ok(peer_addr) = stream.peer_addr() {
+            info = info.with_peer_addr(peer_addr);
+        }
+        if let Ok(local_addr) = stream.local_addr() {
+            info = info.with_local_addr(local_addr);
+        }
+
+        let endpoint = endpoint.clone();
+        let opts = opts.clone().with_connection_info(info);
+        let permit = permits.as_ref().map(|(_, receiver)| receiver.clone());
+        let active = active.clone();
+        task::spawn(async move {
+            let peer_addr = opts.connection_info().and_then(|info| info.peer_addr());
+            let peer_addr = peer_addr.unwrap_or("unknown peer").to_owned();
+            if let Err(e) = accept_with_opts(stream, |req| (*endpoint)(req), opts).await {
+                log::error!("error on connection from {}: {}", peer_addr, e);
+            }
+            if let Some(permit) = permit {
+                let _ = permit.try_recv();
+            }
+            drop(active);
+        });
+    }
+
+    log::trace!("waiting for active connections to finish");
+    drop(active);
+    let _ = finished.recv().await;
+    Ok(())
+}
+
+/// Run `fut` to completion, unless the shutdown is triggered first.
+async fn until_shutdown<F: Future>(shutdown: &Option<Shutdown>, fut: F) -> Option<F::Output> {
+    match shutdown {
+        Some(shutdown) => shutdown.race(fut).await,
+        None => Some(fut.await),
+    }
+}
@@ -0,0 +1,72 @@
+use std::future::Future;
+
+use async_channel::{Receiver, Sender};
+use async_std::future;
+use async_std::task::Poll;
+
+/// A signal to gracefully shut down server connections.
+///
+/// Clones share the same signal. Once [`trigger`](Shutdown::trigger) is
+/// called, connections finish the request they are handling, respond with
+/// `Connection: close`, and stop accepting further requests. Connections
+/// that are waiting for a request are closed immediately.
+///
+/// # Example
+///
+/// ```no_run
+/// use async_h1::server::Shutdown;
+/// use async_h1::ServerOptions;
+///
+/// let shutdown = Shutdown::new();
+/// let opts = ServerOptions::new().with_shutdown(shutdown.clone());
+/// // Pass `opts` to `accept_with_opts` for every connection, then later:
+/// shutdown.trigger();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Sender<()>,
+    receiver: Receiver<()>,
+}
+
+impl Shutdown {
+    /// Create a new signal that hasn't been triggered yet.
+    pub fn new() -> Self {
+        let (sender, receiver) = async_channel::bounded(1);
+        Self { sender, receiver }
+    }
+
+    /// Trigger the shutdown of every connection using this signal.
+    pub fn trigger(&self) {
+        self.sender.close();
+    }
+
+    /// Whether the shutdown has been triggered.
+    pub fn is_triggered(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Wait for the shutdown to be triggered.
+    pub async fn wait(&self) {
+        // Nothing is ever sent, so this only returns once the channel closes.
+        let _ = self.receiver.recv().await;
+    }
+
+    /// Run `fut` to completion, unless the shutdown is triggered first.
+    pub(crate) async fn race<F: Future>(&self, fut: F) -> Option<F::Output> {
+        let mut fut = Box::pin(fut);
+        let mut wait = Box::pin(self.wait());
+        future::poll_fn(|cx| {
+            if wait.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+            fut.as_mut().poll(cx).map(Some)
+        })
+        .await
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
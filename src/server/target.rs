@@ -0,0 +1,42 @@
+/// The request-target of a request, in the form it was received.
+///
+/// Requests carry this in their extensions, since the request URL alone can't
+/// tell the forms apart.
+///
+/// https://tools.ietf.org/html/rfc7230#section-5.3
+///
+/// # Example
+///
+/// ```
+/// use async_h1::server::RequestTarget;
+/// use http_types::Request;
+///
+/// fn is_server_wide_options(req: &Request) -> bool {
+///     req.ext().get::<RequestTarget>() == Some(&RequestTarget::Asterisk)
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestTarget {
+    /// An absolute path with an optional query, such as `/where?q=now`.
+    Origin(String),
+    /// An absolute URI, such as `http://www.example.org/pub/WWW/`. Mostly sent
+    /// to proxies.
+    Absolute(String),
+    /// A host and port, such as `www.example.com:80`. Only used by `CONNECT`
+    /// requests.
+    Authority(String),
+    /// A single `*`. Only used by server-wide `OPTIONS` requests.
+    Asterisk,
+}
+
+impl RequestTarget {
+    /// The request-target as it was received.
+    pub fn as_str(&self) -> &str {
+        match self {
+            RequestTarget::Origin(target)
+            | RequestTarget::Absolute(target)
+            | RequestTarget::Authority(target) => target,
+            RequestTarget::Asterisk => "*",
+        }
+    }
+}
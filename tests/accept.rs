@@ -1,9 +1,12 @@
 mod test_utils;
 mod accept {
     use super::test_utils::TestServer;
-    use async_h1::{client::Encoder, server::ConnectionStatus};
-    use async_std::io::{self, prelude::WriteExt, Cursor};
-    use http_types::{headers::CONNECTION, Body, Request, Response, Result};
+    use async_h1::server::{ConnectionStatus, ExpectContinue, Shutdown};
+    use async_h1::{client::Encoder, Error, ServerOptions};
+    use async_std::io::{self, prelude::ReadExt, prelude::WriteExt, Cursor};
+    use http_types::{headers::CONNECTION, Body, Request, Response, Result, StatusCode, Trailers};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     #[async_std::test]
     async fn basic() -> Result<()> {
@@ -178,4 +181,765 @@ mod accept {
 
         Ok(())
     }
+
+    async fn read_response(reader: &mut (impl io::Read + Unpin)) -> Result<String> {
+        let mut response = vec![0; 1024];
+        let len = reader.read(&mut response).await?;
+        Ok(String::from_utf8(response[..len].to_vec())?)
+    }
+
+    async fn assert_decode_error(request: &[u8], status: StatusCode) -> Result<()> {
+        let mut server = TestServer::new(|_| async { Ok(Response::new(200)) });
+
+        server.write_all(request).await?;
+        let err = server.accept_one().await.unwrap_err();
+        assert_eq!(err.status(), status);
+
+        let response = read_response(&mut server).await?;
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {} ", status)),
+            "unexpected response: {}",
+            response
+        );
+        assert!(response.contains("connection: close\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn decode_errors_are_answered() -> Result<()> {
+        assert_decode_error(
+            b"GET / HTTP/1.1\r\nHost example.com\r\n\r\n",
+            StatusCode::BadRequest,
+        )
+        .await?;
+        assert_decode_error(b"GET / HTTP/1.1\r\n\r\n", StatusCode::BadRequest).await?;
+        assert_decode_error(
+            b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n",
+            StatusCode::BadRequest,
+        )
+        .await?;
+        assert_decode_error(
+            b"FROB / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            StatusCode::NotImplemented,
+        )
+        .await?;
+        assert_decode_error(
+            b"GET / HTTP/2.0\r\nHost: example.com\r\n\r\n",
+            StatusCode::HttpVersionNotSupported,
+        )
+        .await?;
+
+        let long_uri = format!(
+            "GET /{} HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            "a".repeat(9000)
+        );
+        assert_decode_error(long_uri.as_bytes(), StatusCode::UriTooLong).await?;
+
+        let long_header = format!(
+            "GET / HTTP/1.1\r\nHost: example.com\r\nCookie: {}\r\n\r\n",
+            "a".repeat(9000)
+        );
+        assert_decode_error(
+            long_header.as_bytes(),
+            StatusCode::RequestHeaderFieldsTooLarge,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn decode_errors_are_answered_in_request_version() -> Result<()> {
+        for (request, status) in &[
+            (
+                &b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n"[..],
+                StatusCode::BadRequest,
+            ),
+            (&b"FROB / HTTP/1.0\r\n\r\n"[..], StatusCode::NotImplemented),
+        ] {
+            let mut server = TestServer::new(|_| async { Ok(Response::new(200)) });
+            server.write_all(request).await?;
+            assert_eq!(server.accept_one().await.unwrap_err().status(), *status);
+
+            let response = read_response(&mut server).await?;
+            assert!(
+                response.starts_with(&format!("HTTP/1.0 {} ", status)),
+                "unexpected response: {}",
+                response
+            );
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn http_1_0_closes_by_default() -> Result<()> {
+        let mut server = TestServer::new(|_| async {
+            let mut response = Response::new(200);
+            response.set_body("hello");
+            Ok(response)
+        });
+
+        server.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("content-length: 5\r\n"));
+        assert!(!response.contains("connection: "));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn http_1_0_keep_alive() -> Result<()> {
+        let mut server = TestServer::new(|_| async {
+            let mut response = Response::new(200);
+            response.set_body("hello");
+            Ok(response)
+        });
+
+        server
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("connection: keep-alive\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn http_1_0_streaming_body_is_close_delimited() -> Result<()> {
+        let mut server = TestServer::new(|_| async {
+            let mut response = Response::new(200);
+            response.set_body(Body::from_reader(Cursor::new("hello"), None));
+            Ok(response)
+        });
+
+        server
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(!response.contains("transfer-encoding"));
+        assert!(!response.contains("connection: keep-alive"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn max_requests() -> Result<()> {
+        let opts = ServerOptions::new().with_max_requests(2);
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(200)) }, opts);
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+        let response = read_response(&mut server).await?;
+        assert!(response.contains("keep-alive: max=1\r\n"));
+        assert!(!response.contains("connection: close"));
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+        let response = read_response(&mut server).await?;
+        assert!(response.contains("connection: close\r\n"));
+        assert!(!response.contains("keep-alive"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn keep_alive_timeout() -> Result<()> {
+        let opts = ServerOptions::new()
+            .with_keep_alive_timeout(Duration::from_millis(100))
+            .with_max_requests(10);
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(200)) }, opts);
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+        let response = read_response(&mut server).await?;
+        assert!(response.contains("keep-alive: max=9\r\n"));
+
+        // The client never sends a second request.
+        let idle = async_std::future::timeout(Duration::from_secs(5), server.accept_one());
+        assert_eq!(idle.await??, ConnectionStatus::Close);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn keep_alive_timeout_is_advertised() -> Result<()> {
+        let opts = ServerOptions::new().with_keep_alive_timeout(Duration::from_millis(5500));
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(200)) }, opts);
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+        let response = read_response(&mut server).await?;
+        assert!(response.contains("keep-alive: timeout=5\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn body_timeout() -> Result<()> {
+        let timeout = Duration::from_millis(100);
+        let opts = ServerOptions::new().with_body_timeout(timeout);
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(200)) }, opts);
+
+        // The client declares ten bytes of body but only ever sends three.
+        server
+            .write_all(b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 10\r\n\r\nabc")
+            .await?;
+
+        let err = server.accept_one().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Error>(),
+            Some(&Error::BodyTimeout(timeout))
+        );
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn body_timeout_ignored_by_endpoint() -> Result<()> {
+        let timeout = Duration::from_millis(100);
+        let opts = ServerOptions::new().with_body_timeout(timeout);
+        let mut server = TestServer::with_opts(
+            |mut req: Request| async move {
+                assert!(req.body_bytes().await.is_err());
+                Ok(Response::new(200))
+            },
+            opts,
+        );
+
+        // The client declares ten bytes of body but only ever sends three.
+        server
+            .write_all(b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 10\r\n\r\nabc")
+            .await?;
+
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 200 "));
+        assert!(response.contains("connection: close\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn fixed_length_body_over_drain_limit() -> Result<()> {
+        let opts = ServerOptions::new().with_max_drain_length(100);
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(401)) }, opts);
+
+        let request_str = format!(
+            "POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 100000\r\n\r\n{}",
+            "|".repeat(100000)
+        );
+        server.write_all(request_str.as_bytes()).await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 401 "));
+        assert!(response.contains("connection: close\r\n"));
+        assert!(!server.all_read());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn fixed_length_body_within_drain_limit() -> Result<()> {
+        let opts = ServerOptions::new().with_max_drain_length(100);
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(401)) }, opts);
+
+        let request_str = format!(
+            "POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 100\r\n\r\n{}",
+            "|".repeat(100)
+        );
+        server.write_all(request_str.as_bytes()).await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+        assert!(server.all_read());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn chunked_body_over_drain_limit() -> Result<()> {
+        let opts = ServerOptions::new().with_max_drain_length(100);
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(401)) }, opts);
+
+        let mut request = Request::post("http://example.com/");
+        request.set_body(Body::from_reader(Cursor::new(vec![b'|'; 100000]), None));
+        server.write_request(request).await?;
+
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+        assert!(!server.all_read());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn chunked_body_over_max_body_length() -> Result<()> {
+        let opts = ServerOptions::new().with_max_body_length(100);
+        let mut server = TestServer::with_opts(
+            |mut req: Request| async move {
+                match req.body_bytes().await {
+                    Ok(_) => Ok(Response::new(200)),
+                    Err(_) => Ok(Response::new(413)),
+                }
+            },
+            opts,
+        );
+
+        let mut request = Request::post("http://example.com/");
+        request.set_body(Body::from_reader(Cursor::new(vec![b'|'; 1000]), None));
+        server.write_request(request).await?;
+
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 413 "));
+        assert!(response.contains("connection: close\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn chunked_body_over_max_body_length_returned_by_endpoint() -> Result<()> {
+        let opts = ServerOptions::new().with_max_body_length(100);
+        let mut server = TestServer::with_opts(
+            |mut req: Request| async move {
+                let mut body = Vec::new();
+                req.read_to_end(&mut body).await?;
+                Ok(Response::new(200))
+            },
+            opts,
+        );
+
+        let mut request = Request::post("http://example.com/");
+        request.set_body(Body::from_reader(Cursor::new(vec![b'|'; 1000]), None));
+        server.write_request(request).await?;
+
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 413 "));
+        assert!(response.contains("connection: close\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn unread_chunked_body_over_max_body_length() -> Result<()> {
+        let opts = ServerOptions::new().with_max_body_length(100);
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(200)) }, opts);
+
+        let mut request = Request::post("http://example.com/");
+        request.set_body(Body::from_reader(Cursor::new(vec![b'|'; 1000]), None));
+        server.write_request(request).await?;
+
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shutdown_closes_idle_connection() -> Result<()> {
+        let shutdown = Shutdown::new();
+        let opts = ServerOptions::new().with_shutdown(shutdown.clone());
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(200)) }, opts);
+
+        async_std::task::spawn(async move {
+            async_std::task::sleep(Duration::from_millis(50)).await;
+            shutdown.trigger();
+        });
+
+        let idle = async_std::future::timeout(Duration::from_secs(5), server.accept_one());
+        assert_eq!(idle.await??, ConnectionStatus::Close);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shutdown_finishes_in_flight_request() -> Result<()> {
+        let shutdown = Shutdown::new();
+        let opts = ServerOptions::new().with_shutdown(shutdown.clone());
+        let mut server = TestServer::with_opts(
+            move |_| {
+                let shutdown = shutdown.clone();
+                async move {
+                    shutdown.trigger();
+                    Ok(Response::new(200))
+                }
+            },
+            opts,
+        );
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("connection: close\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn endpoint_error_is_answered() -> Result<()> {
+        let mut server = TestServer::new(|_| async {
+            Err::<Response, _>(http_types::Error::from_str(404, "not here"))
+        });
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("content-length: 0\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn endpoint_error_with_error_handler() -> Result<()> {
+        let opts = ServerOptions::new().with_error_handler(|err| {
+            let mut res = Response::new(err.status());
+            res.set_body(format!("oops: {}", err));
+            res
+        });
+        let mut server = TestServer::with_opts(
+            |_| async { Err::<Response, _>(http_types::Error::from_str(503, "busy")) },
+            opts,
+        );
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.ends_with("\r\n\r\noops: busy"));
+
+        Ok(())
+    }
+
+    async fn panicking_endpoint(_req: Request) -> Result<Response> {
+        panic!("boom");
+    }
+
+    #[async_std::test]
+    async fn endpoint_panic_is_answered() -> Result<()> {
+        let opts = ServerOptions::new().with_catch_panics(true);
+        let mut server = TestServer::with_opts(panicking_endpoint, opts);
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(response.contains("connection: close\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn endpoint_panic_with_panic_handler() -> Result<()> {
+        let message = Arc::new(Mutex::new(None));
+        let opts = ServerOptions::new().with_panic_handler({
+            let message = message.clone();
+            move |payload| {
+                let payload = payload.downcast_ref::<&str>().map(|s| s.to_string());
+                *message.lock().unwrap() = payload;
+            }
+        });
+        let mut server = TestServer::with_opts(panicking_endpoint, opts);
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert_eq!(message.lock().unwrap().as_deref(), Some("boom"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn final_status_without_continue_closes() -> Result<()> {
+        let mut server = TestServer::new(|_| async { Ok(Response::new(413)) });
+
+        server
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 10\r\nExpect: 100-continue\r\n\r\n",
+            )
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(response.contains("connection: close\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn continue_sent_before_response() -> Result<()> {
+        let mut server = TestServer::new(|req: Request| async move {
+            req.ext().get::<ExpectContinue>().unwrap().send();
+            Ok(Response::new(200))
+        });
+
+        server
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 10\r\nExpect: 100-continue\r\n\r\n0123456789",
+            )
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"));
+        assert_eq!(response.matches("100 Continue").count(), 1);
+        assert!(server.all_read());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn body_read_without_continue_once_response_starts() -> Result<()> {
+        let opts = ServerOptions::new().with_auto_continue(false);
+        let mut server = TestServer::with_opts(
+            |req: Request| async move {
+                let mut res = Response::new(200);
+                res.set_body(Body::from_reader(req, None));
+                Ok(res)
+            },
+            opts,
+        );
+
+        server
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 10\r\nExpect: 100-continue\r\n\r\n0123456789",
+            )
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("0123456789\r\n0\r\n\r\n"));
+        assert!(!response.contains("100 Continue"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn request_body_held_by_endpoint() -> Result<()> {
+        let held = Arc::new(Mutex::new(None));
+        let held_clone = held.clone();
+        let mut server = TestServer::new(move |req: Request| {
+            let held = held_clone.clone();
+            async move {
+                *held.lock().unwrap() = Some(req);
+                Ok(Response::new(200))
+            }
+        });
+
+        server
+            .write_all(b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\nhello")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        // The body can still be read after the response went out.
+        let mut req = held.lock().unwrap().take().unwrap();
+        assert_eq!(req.body_string().await?, "hello");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn echoed_request_body_keeps_connection_alive() -> Result<()> {
+        let mut server = TestServer::new(|mut req: Request| async move {
+            let mut res = Response::new(200);
+            res.set_body(req.take_body());
+            Ok(res)
+        });
+
+        server
+            .write_all(b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+        let response = read_response(&mut server).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+        assert!(server.all_read());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn many_headers_on_a_kept_alive_connection() -> Result<()> {
+        let opts = ServerOptions::new().with_max_headers(200);
+        let mut server = TestServer::with_opts(
+            |req: Request| async move {
+                assert_eq!(req["x-149"], "149");
+                Ok(Response::new(200))
+            },
+            opts,
+        );
+
+        let mut request = "GET / HTTP/1.1\r\nHost: example.com\r\n".to_string();
+        for i in 0..150 {
+            request.push_str(&format!("x-{}: {}\r\n", i, i));
+        }
+        request.push_str("\r\n");
+
+        for _ in 0..2 {
+            server.write_all(request.as_bytes()).await?;
+            assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+            let response = read_response(&mut server).await?;
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+
+        Ok(())
+    }
+
+    async fn endpoint_with_trailers(_req: Request) -> Result<Response> {
+        let mut res = Response::new(200);
+        res.set_body("hello");
+        res.insert_header("Trailer", "checksum");
+        let mut trailers = Trailers::new();
+        trailers.insert("Checksum", "abc");
+        res.send_trailers().send(trailers).await;
+        Ok(res)
+    }
+
+    #[async_std::test]
+    async fn trailers_with_te_trailers() -> Result<()> {
+        let mut server = TestServer::new(endpoint_with_trailers);
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\nTE: trailers\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+
+        let response = read_response(&mut server).await?;
+        assert!(response.contains("trailer: checksum\r\n"));
+        assert!(response.ends_with("\r\n5\r\nhello\r\n0\r\nchecksum: abc\r\n\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn trailers_without_te_trailers() -> Result<()> {
+        let mut server = TestServer::new(endpoint_with_trailers);
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+
+        let response = read_response(&mut server).await?;
+        assert!(!response.contains("trailer:"));
+        assert!(response.contains("content-length: 5\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn proxy_protocol() -> Result<()> {
+        let opts = ServerOptions::new().with_proxy_protocol(true);
+        let mut server = TestServer::with_opts(
+            |req: Request| async move {
+                let mut res = Response::new(200);
+                res.set_body(req.peer_addr().unwrap_or("none").to_string());
+                Ok(res)
+            },
+            opts,
+        );
+
+        server
+            .write_all(b"PROXY TCP4 203.0.113.7 192.0.2.1 51234 80\r\nGET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+        let response = read_response(&mut server).await?;
+        assert!(response.ends_with("\r\n\r\n203.0.113.7:51234"));
+
+        // Only the first request is preceded by the header.
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+        let response = read_response(&mut server).await?;
+        assert!(response.ends_with("\r\n\r\n203.0.113.7:51234"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn missing_proxy_protocol_header() -> Result<()> {
+        let opts = ServerOptions::new().with_proxy_protocol(true);
+        let mut server = TestServer::with_opts(|_| async { Ok(Response::new(200)) }, opts);
+
+        server
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await?;
+        let err = server.accept_one().await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BadRequest);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn pipelined_requests() -> Result<()> {
+        let mut server = TestServer::new(|mut req: Request| async move {
+            let mut res = Response::new(200);
+            res.set_body(req.body_string().await?);
+            Ok(res)
+        });
+
+        server
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\nfirst\
+                  POST / HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  6\r\nsecond\r\n0\r\n\r\n\
+                  GET / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            )
+            .await?;
+        server.close();
+
+        for body in &["first", "second", ""] {
+            assert_eq!(server.accept_one().await?, ConnectionStatus::KeepAlive);
+            let response = read_response(&mut server).await?;
+            assert!(response.ends_with(&format!("\r\n\r\n{}", body)));
+        }
+        assert_eq!(server.accept_one().await?, ConnectionStatus::Close);
+        assert!(server.all_read());
+
+        Ok(())
+    }
 }
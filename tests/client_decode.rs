@@ -4,7 +4,8 @@ mod client_decode {
     use std::io::Write;
 
     use super::test_utils::CloseableCursor;
-    use async_h1::client;
+    use async_h1::client::{self, ClientOptions};
+    use async_h1::{Error, ParseMode};
     use async_std::io::Cursor;
     use http_types::headers;
     use http_types::Response;
@@ -26,7 +27,9 @@ mod client_decode {
         ])
         .await?;
 
-        assert!(res.header(&headers::DATE).is_some());
+        let date = res.header(&headers::DATE).unwrap().as_str();
+        assert_eq!(date.len(), "Thu, 01 Jan 1970 00:00:00 GMT".len());
+        assert!(date.ends_with(" GMT"));
         Ok(())
     }
 
@@ -90,4 +93,44 @@ mod client_decode {
 
         Ok(())
     }
+
+    #[async_std::test]
+    async fn head_limits() -> Result<()> {
+        let head = "HTTP/1.1 200 OK\r\ncontent-length: 0\r\nx-a: 1\r\nx-b: 2\r\n\r\n";
+
+        let opts = ClientOptions::new().with_max_head_length(32);
+        let err = client::decode_with_opts(Cursor::new(head), &opts)
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::HeadTooLarge(32)));
+
+        let opts = ClientOptions::new().with_max_headers(2);
+        let err = client::decode_with_opts(Cursor::new(head), &opts)
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::TooManyHeaders(2)));
+
+        let opts = ClientOptions::new().with_max_headers(3);
+        let res = client::decode_with_opts(Cursor::new(head), &opts).await?;
+        assert_eq!(res["x-b"], "2");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn parse_modes() -> Result<()> {
+        let head = "HTTP/1.1  200 OK \nx-folded: a\n b\ncontent-length : 0\n\n";
+
+        let res = client::decode(Cursor::new(head)).await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res["x-folded"], "a b");
+        assert_eq!(res["content-length"], "0");
+
+        let opts = ClientOptions::new().with_parse_mode(ParseMode::Strict);
+        assert!(client::decode_with_opts(Cursor::new(head), &opts)
+            .await
+            .is_err());
+
+        Ok(())
+    }
 }
@@ -5,7 +5,7 @@ mod client_encode {
     use client::Encoder;
     use http_types::Body;
     use http_types::Result;
-    use http_types::{Method, Request, Url};
+    use http_types::{Method, Request, Trailers, Url};
     use pretty_assertions::assert_eq;
 
     async fn encode_to_string(request: Request, len: usize) -> http_types::Result<String> {
@@ -109,12 +109,8 @@ mod client_encode {
                 "content-type: application/octet-stream",
                 "transfer-encoding: chunked",
                 "",
-                "5",
-                "hello",
-                "5",
-                " worl",
-                "1",
-                "d",
+                "B",
+                "hello world",
                 "0",
                 "",
                 "",
@@ -160,14 +156,8 @@ mod client_encode {
                 "content-type: application/octet-stream",
                 "transfer-encoding: chunked",
                 "",
-                "1A",
-                "this response is more than",
-                "1A",
-                " 32 bytes long in order to",
-                "1A",
-                " require a second hex digi",
-                "1",
-                "t",
+                "4F",
+                "this response is more than 32 bytes long in order to require a second hex digit",
                 "0",
                 "",
                 "",
@@ -177,4 +167,37 @@ mod client_encode {
 
         Ok(())
     }
+
+    #[async_std::test]
+    async fn client_encode_request_with_trailers() -> Result<()> {
+        let url = Url::parse("http://localhost:8080").unwrap();
+        let mut req = Request::new(Method::Post, url);
+        req.set_body(Body::from_reader(Cursor::new("hello"), None));
+        req.insert_header("Trailer", "digest");
+
+        let mut trailers = Trailers::new();
+        trailers.insert("Digest", "sha-256=abc");
+        req.send_trailers().send(trailers).await;
+
+        assert_encoded(
+            100,
+            req,
+            vec![
+                "POST / HTTP/1.1",
+                "host: localhost:8080",
+                "content-type: application/octet-stream",
+                "trailer: digest",
+                "transfer-encoding: chunked",
+                "",
+                "5",
+                "hello",
+                "0",
+                "digest: sha-256=abc",
+                "",
+                "",
+            ],
+        )
+        .await;
+        Ok(())
+    }
 }
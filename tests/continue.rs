@@ -1,5 +1,7 @@
 mod test_utils;
 
+use async_h1::server::ExpectContinue;
+use async_h1::ServerOptions;
 use async_std::{io, prelude::*, task};
 use http_types::Result;
 use std::time::Duration;
@@ -52,3 +54,84 @@ async fn test_without_expect_when_not_reading_body() -> Result<()> {
 
     Ok(())
 }
+
+#[async_std::test]
+async fn test_explicit_continue_without_auto_continue() -> Result<()> {
+    let (mut client, server) = TestIO::new();
+    client.write_all(REQUEST_WITH_EXPECT).await?;
+
+    let opts = ServerOptions::new().with_auto_continue(false);
+    let (mut request, _) = async_h1::server::decode_with_opts(server, &opts)
+        .await?
+        .unwrap();
+    let expect = request.ext().get::<ExpectContinue>().unwrap().clone();
+
+    let join_handle = task::spawn(async move {
+        let mut string = String::new();
+        request.read_to_string(&mut string).await?;
+        io::Result::Ok(string)
+    });
+
+    task::sleep(SLEEP_DURATION).await;
+
+    assert_eq!("", &client.read.to_string()); // reading didn't send 100-continue
+    assert!(!expect.is_sent());
+
+    expect.send();
+    task::sleep(SLEEP_DURATION).await;
+
+    assert_eq!("HTTP/1.1 100 Continue\r\n\r\n", &client.read.to_string());
+
+    client.write_all(b"0123456789").await?;
+
+    assert_eq!("0123456789", &join_handle.await?);
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn test_reading_body_without_send_waits() -> Result<()> {
+    let (mut client, server) = TestIO::new();
+    client.write_all(REQUEST_WITH_EXPECT).await?;
+
+    let opts = ServerOptions::new().with_auto_continue(false);
+    let (mut request, _) = async_h1::server::decode_with_opts(server, &opts)
+        .await?
+        .unwrap();
+    let expect = request.ext().get::<ExpectContinue>().unwrap().clone();
+
+    let mut join_handle = task::spawn(async move {
+        let mut string = String::new();
+        request.read_to_string(&mut string).await?;
+        io::Result::Ok(string)
+    });
+
+    // the client stops waiting and sends the body anyway
+    client.write_all(b"0123456789").await?;
+    let pending = async_std::future::timeout(SLEEP_DURATION, &mut join_handle).await;
+    assert!(pending.is_err()); // the read holds off until the endpoint decides
+
+    assert_eq!("", &client.read.to_string());
+    assert!(!expect.is_sent());
+
+    expect.send();
+    assert_eq!("0123456789", &join_handle.await?);
+    assert_eq!("HTTP/1.1 100 Continue\r\n\r\n", &client.read.to_string());
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn test_unsupported_expectation() -> Result<()> {
+    let (mut client, server) = TestIO::new();
+    client
+        .write_all(
+            b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 10\r\nExpect: teapot\r\n\r\n",
+        )
+        .await?;
+
+    let err = async_h1::server::decode(server).await.unwrap_err();
+    assert_eq!(err.status(), 417);
+
+    Ok(())
+}
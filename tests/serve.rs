@@ -0,0 +1,84 @@
+use async_h1::server::{serve, Shutdown};
+use async_h1::ServerOptions;
+use async_std::future::timeout;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task;
+use http_types::{Method, Request, Response, Result, Url};
+use std::time::Duration;
+
+async fn get(addr: std::net::SocketAddr) -> Result<Response> {
+    let stream = TcpStream::connect(addr).await?;
+    let url = Url::parse(&format!("http://{}/", addr))?;
+    async_h1::connect(stream, Request::new(Method::Get, url)).await
+}
+
+#[async_std::test]
+async fn serves_connections_until_shutdown() -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let addr = listener.local_addr()?;
+    let shutdown = Shutdown::new();
+    let opts = ServerOptions::new().with_shutdown(shutdown.clone());
+
+    let server = task::spawn(serve(listener, |_| async { Ok(Response::new(200)) }, opts));
+
+    assert_eq!(get(addr).await?.status(), 200);
+    assert_eq!(get(addr).await?.status(), 200);
+
+    shutdown.trigger();
+    timeout(Duration::from_secs(5), server).await??;
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn zero_max_connections_is_unlimited() -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let addr = listener.local_addr()?;
+    let shutdown = Shutdown::new();
+    let opts = ServerOptions::new()
+        .with_shutdown(shutdown.clone())
+        .with_max_connections(0);
+    assert_eq!(opts.max_connections(), None);
+
+    let server = task::spawn(serve(listener, |_| async { Ok(Response::new(200)) }, opts));
+
+    assert_eq!(get(addr).await?.status(), 200);
+
+    shutdown.trigger();
+    timeout(Duration::from_secs(5), server).await??;
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn limits_concurrent_connections() -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let addr = listener.local_addr()?;
+    let shutdown = Shutdown::new();
+    let opts = ServerOptions::new()
+        .with_shutdown(shutdown.clone())
+        .with_max_connections(1);
+
+    let server = task::spawn(serve(listener, |_| async { Ok(Response::new(200)) }, opts));
+
+    // Hold the only connection slot open without sending a request.
+    let idle = TcpStream::connect(addr).await?;
+    task::sleep(Duration::from_millis(50)).await;
+
+    let mut second = task::spawn(get(addr));
+    assert!(timeout(Duration::from_millis(200), &mut second)
+        .await
+        .is_err());
+
+    // Closing the first connection frees up the slot.
+    drop(idle);
+    assert_eq!(
+        timeout(Duration::from_secs(5), second).await??.status(),
+        200
+    );
+
+    shutdown.trigger();
+    timeout(Duration::from_secs(5), server).await??;
+
+    Ok(())
+}
@@ -1,19 +1,30 @@
 mod test_utils;
 mod server_decode {
     use super::test_utils::TestIO;
+    use async_h1::server::ConnectionInfo;
+    use async_h1::{Error, ParseMode, ServerOptions};
     use async_std::io::prelude::*;
     use http_types::headers::TRANSFER_ENCODING;
     use http_types::Request;
     use http_types::Result;
+    use http_types::StatusCode;
     use http_types::Url;
     use pretty_assertions::assert_eq;
+    use std::time::Duration;
 
     async fn decode_lines(lines: Vec<&str>) -> Result<Option<Request>> {
+        decode_lines_with_opts(lines, ServerOptions::default()).await
+    }
+
+    async fn decode_lines_with_opts(
+        lines: Vec<&str>,
+        opts: ServerOptions,
+    ) -> Result<Option<Request>> {
         let s = lines.join("\r\n");
         let (mut client, server) = TestIO::new();
         client.write_all(s.as_bytes()).await?;
         client.close();
-        async_h1::server::decode(server)
+        async_h1::server::decode_with_opts(server, &opts)
             .await
             .map(|r| r.map(|(r, _)| r))
     }
@@ -125,4 +136,351 @@ mod server_decode {
 
         Ok(())
     }
+
+    #[async_std::test]
+    async fn head_too_large() -> Result<()> {
+        let cookie = format!("cookie: {}", "x".repeat(100));
+        let lines = vec!["GET / HTTP/1.1", "host: example.com", &cookie, "", ""];
+
+        let err =
+            decode_lines_with_opts(lines.clone(), ServerOptions::new().with_max_head_length(64))
+                .await
+                .unwrap_err();
+        assert_eq!(err.status(), StatusCode::RequestHeaderFieldsTooLarge);
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::HeadTooLarge(64)));
+
+        let request = decode_lines_with_opts(lines, ServerOptions::new().with_max_head_length(256))
+            .await?
+            .unwrap();
+        assert_eq!(request["cookie"].as_str().len(), 100);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn head_split_across_reads() -> Result<()> {
+        let head = format!(
+            "GET / HTTP/1.1\r\nhost: example.com\r\ncookie: {}\r\n\r\n",
+            "x".repeat(10_000)
+        );
+        let (mut client, server) = TestIO::new();
+        let writer = async_std::task::spawn(async move {
+            for part in head.as_bytes().chunks(1000) {
+                client.write_all(part).await?;
+                async_std::task::sleep(Duration::from_millis(1)).await;
+            }
+            client.close();
+            std::io::Result::Ok(())
+        });
+
+        let opts = ServerOptions::new().with_max_head_length(16 * 1024);
+        let (request, _) = async_h1::server::decode_with_opts(server, &opts)
+            .await?
+            .unwrap();
+        writer.await?;
+        assert_eq!(request["cookie"].as_str().len(), 10_000);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn too_many_headers() -> Result<()> {
+        let lines = vec![
+            "GET / HTTP/1.1",
+            "host: example.com",
+            "a: 1",
+            "b: 2",
+            "",
+            "",
+        ];
+
+        let err = decode_lines_with_opts(lines.clone(), ServerOptions::new().with_max_headers(2))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::RequestHeaderFieldsTooLarge);
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::TooManyHeaders(2)));
+
+        let request = decode_lines_with_opts(lines, ServerOptions::new().with_max_headers(3))
+            .await?
+            .unwrap();
+        assert_eq!(request["b"], "2");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn http_1_0() -> Result<()> {
+        let request = decode_lines(vec!["GET /foo HTTP/1.0", "", ""])
+            .await?
+            .unwrap();
+
+        assert_eq!(request.version(), Some(http_types::Version::Http1_0));
+        assert_eq!(request.url().path(), "/foo");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn content_length_over_max_body_length() -> Result<()> {
+        let opts = ServerOptions::new().with_max_body_length(4);
+        let lines = vec![
+            "POST / HTTP/1.1",
+            "host: example.com",
+            "content-length: 5",
+            "",
+            "hello",
+        ];
+
+        let err = decode_lines_with_opts(lines, opts).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::PayloadTooLarge);
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::BodyTooLarge(4)));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn chunked_over_max_body_length() -> Result<()> {
+        let opts = ServerOptions::new().with_max_body_length(4);
+        let lines = vec![
+            "POST / HTTP/1.1",
+            "host: example.com",
+            "transfer-encoding: chunked",
+            "",
+            "3",
+            "hel",
+            "2",
+            "lo",
+            "0",
+            "",
+            "",
+        ];
+
+        let mut request = decode_lines_with_opts(lines, opts).await?.unwrap();
+        let err = request.body_string().await.unwrap_err();
+        assert!(err.to_string().contains("at most 4 bytes"));
+
+        Ok(())
+    }
+
+    async fn assert_rejected(headers: Vec<&str>, status: u16) {
+        let mut lines = vec!["POST / HTTP/1.1", "host: localhost:8080"];
+        lines.extend(headers);
+        lines.extend(vec!["", "0", "", ""]);
+        let err = decode_lines(lines).await.unwrap_err();
+        assert_eq!(err.status(), status);
+    }
+
+    async fn decode_body(headers: Vec<&str>, body: &str) -> Result<String> {
+        let mut lines = vec!["POST / HTTP/1.1", "host: localhost:8080"];
+        lines.extend(headers);
+        lines.extend(vec!["", body]);
+        let mut request = decode_lines(lines).await?.unwrap();
+        request.body_string().await
+    }
+
+    #[async_std::test]
+    async fn chunked_after_other_codings() -> Result<()> {
+        let body = decode_body(
+            vec!["transfer-encoding: gzip, chunked"],
+            "5\r\nhello\r\n0\r\n\r\n",
+        );
+        assert_eq!(body.await?, "hello");
+
+        let body = decode_body(
+            vec!["transfer-encoding: gzip", "transfer-encoding: Chunked"],
+            "5\r\nhello\r\n0\r\n\r\n",
+        );
+        assert_eq!(body.await?, "hello");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn chunked_not_final() {
+        assert_rejected(vec!["transfer-encoding: chunked, gzip"], 400).await;
+        assert_rejected(
+            vec!["transfer-encoding: chunked", "transfer-encoding: gzip"],
+            400,
+        )
+        .await;
+        assert_rejected(vec!["transfer-encoding: gzip"], 400).await;
+        assert_rejected(vec!["transfer-encoding: ,"], 400).await;
+    }
+
+    #[async_std::test]
+    async fn chunked_more_than_once() {
+        assert_rejected(vec!["transfer-encoding: chunked, chunked"], 400).await;
+        assert_rejected(
+            vec!["transfer-encoding: chunked", "transfer-encoding: chunked"],
+            400,
+        )
+        .await;
+    }
+
+    #[async_std::test]
+    async fn unknown_transfer_coding() {
+        assert_rejected(vec!["transfer-encoding: foo, chunked"], 501).await;
+        assert_rejected(vec!["transfer-encoding: identity"], 501).await;
+        assert_rejected(vec!["transfer-encoding: chunked;ext=1"], 501).await;
+    }
+
+    #[async_std::test]
+    async fn transfer_encoding_with_content_length() {
+        assert_rejected(vec!["transfer-encoding: chunked", "content-length: 5"], 400).await;
+        assert_rejected(
+            vec!["content-length: 5", "transfer-encoding: gzip, chunked"],
+            400,
+        )
+        .await;
+    }
+
+    #[async_std::test]
+    async fn transfer_encoding_in_http_1_0() {
+        let err = decode_lines(vec![
+            "POST / HTTP/1.0",
+            "transfer-encoding: chunked",
+            "",
+            "0",
+            "",
+            "",
+        ])
+        .await
+        .unwrap_err();
+        assert_eq!(err.status(), 400);
+    }
+
+    #[async_std::test]
+    async fn identical_content_lengths() -> Result<()> {
+        let body = decode_body(vec!["content-length: 5", "content-length: 5"], "hello");
+        assert_eq!(body.await?, "hello");
+
+        let body = decode_body(vec!["content-length: 5, 5"], "hello");
+        assert_eq!(body.await?, "hello");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn conflicting_content_lengths() {
+        assert_rejected(vec!["content-length: 5", "content-length: 6"], 400).await;
+        assert_rejected(vec!["content-length: 5, 6"], 400).await;
+        assert_rejected(vec!["content-length: 5,"], 400).await;
+    }
+
+    #[async_std::test]
+    async fn invalid_content_length() {
+        for value in &[
+            "+5",
+            "-5",
+            "0x5",
+            "5a",
+            "5 5",
+            "",
+            "99999999999999999999999",
+        ] {
+            let header = format!("content-length: {}", value);
+            assert_rejected(vec![&header], 400).await;
+        }
+    }
+
+    async fn decode_with_parse_mode(head: &str, parse_mode: ParseMode) -> Result<Option<Request>> {
+        let (mut client, server) = TestIO::new();
+        client.write_all(head.as_bytes()).await?;
+        client.close();
+        let opts = ServerOptions::new().with_parse_mode(parse_mode);
+        async_h1::server::decode_with_opts(server, &opts)
+            .await
+            .map(|r| r.map(|(r, _)| r))
+    }
+
+    #[async_std::test]
+    async fn strict_parsing() {
+        for head in &[
+            "GET / HTTP/1.1\nhost: example.com\n\n",
+            "GET / HTTP/1.1\r\nhost: example.com\n\r\n",
+            "GET / HTTP/1.1\r\nhost: example.com\r\nx-folded: a\r\n b\r\n\r\n",
+            "GET / HTTP/1.1\r\nhost : example.com\r\n\r\n",
+            "GET  / HTTP/1.1\r\nhost: example.com\r\n\r\n",
+            "GET / HTTP/1.1 \r\nhost: example.com\r\n\r\n",
+        ] {
+            let err = decode_with_parse_mode(head, ParseMode::Strict)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status(), StatusCode::BadRequest, "{:?}", head);
+        }
+    }
+
+    #[async_std::test]
+    async fn lenient_parsing() -> Result<()> {
+        let request = decode_with_parse_mode(
+            "GET  /  HTTP/1.1 \nhost : example.com\nx-folded: a\n\t b\n\n",
+            ParseMode::Lenient,
+        )
+        .await?
+        .unwrap();
+        assert_eq!(request.url().as_str(), "http://example.com/");
+        assert_eq!(request["x-folded"], "a b");
+
+        let mut request = decode_with_parse_mode(
+            "POST / HTTP/1.1\nhost: example.com\ntransfer-encoding: chunked\n\n5\r\nhello\r\n0\r\nx-trailer : a\n b\n\n",
+            ParseMode::Lenient,
+        )
+        .await?
+        .unwrap();
+        let trailers = request.recv_trailers();
+        assert_eq!(request.body_string().await?, "hello");
+        assert_eq!(trailers.await.unwrap()["x-trailer"], "a b");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn strict_trailers() -> Result<()> {
+        let mut request = decode_with_parse_mode(
+            "POST / HTTP/1.1\r\nhost: example.com\r\ntransfer-encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nx-trailer: a\n\r\n",
+            ParseMode::Strict,
+        )
+        .await?
+        .unwrap();
+        assert!(request.body_string().await.is_err());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn connection_info() -> Result<()> {
+        let info = ConnectionInfo::new()
+            .with_peer_addr("203.0.113.7:51234")
+            .with_local_addr("192.0.2.1:443")
+            .with_tls(true)
+            .with_alpn_protocol("http/1.1")
+            .with_server_name("example.com");
+        let opts = ServerOptions::new().with_connection_info(info.clone());
+
+        let request = decode_lines_with_opts(
+            vec!["GET /path HTTP/1.1", "host: example.com:443", "", ""],
+            opts.clone(),
+        )
+        .await?
+        .unwrap();
+        assert_eq!(request.url().as_str(), "https://example.com/path");
+        assert_eq!(request.peer_addr(), Some("203.0.113.7:51234"));
+        assert_eq!(request.local_addr(), Some("192.0.2.1:443"));
+        assert_eq!(request.ext().get::<ConnectionInfo>(), Some(&info));
+
+        let request = decode_lines_with_opts(
+            vec![
+                "GET http://example.com/path HTTP/1.1",
+                "host: example.com",
+                "",
+                "",
+            ],
+            opts,
+        )
+        .await?
+        .unwrap();
+        assert_eq!(request.url().as_str(), "http://example.com/path");
+
+        Ok(())
+    }
 }
@@ -1,11 +1,14 @@
 mod server_encode {
+    use std::pin::Pin;
+
     use async_h1::server::Encoder;
-    use async_std::io::Cursor;
     use async_std::io::ReadExt;
+    use async_std::io::{self, BufRead, BufReader, Cursor, IoSlice, Write};
+    use async_std::task::{Context, Poll};
     use http_types::Body;
     use http_types::Result;
     use http_types::StatusCode;
-    use http_types::{Method, Response};
+    use http_types::{Method, Response, Trailers};
     use pretty_assertions::assert_eq;
 
     async fn encode_to_string(
@@ -13,8 +16,11 @@ mod server_encode {
         len: usize,
         method: Method,
     ) -> http_types::Result<String> {
+        read_to_string(Encoder::new(response, method), len).await
+    }
+
+    async fn read_to_string(mut encoder: Encoder, len: usize) -> http_types::Result<String> {
         let mut buf = vec![];
-        let mut encoder = Encoder::new(response, method);
         loop {
             let mut inner_buf = vec![0; len];
             let bytes = encoder.read(&mut inner_buf).await?;
@@ -101,12 +107,8 @@ mod server_encode {
                 "date: {DATE}",
                 "transfer-encoding: chunked",
                 "",
-                "5",
-                "hello",
-                "5",
-                " worl",
-                "1",
-                "d",
+                "B",
+                "hello world",
                 "0",
                 "",
                 "",
@@ -116,6 +118,149 @@ mod server_encode {
         Ok(())
     }
 
+    /// A streaming "hello world" body, read in the given pieces.
+    fn chunked_body(pieces: &[&'static str]) -> Response {
+        let mut body: Box<dyn BufRead + Unpin + Send + Sync> = Box::new(Cursor::new(""));
+        for piece in pieces {
+            body = Box::new(body.chain(Cursor::new(*piece)));
+        }
+        let mut res = Response::new(StatusCode::Ok);
+        res.set_body(Body::from_reader(BufReader::new(body), None));
+        res
+    }
+
+    async fn encoded_body(encoder: Encoder, len: usize) -> String {
+        let encoded = read_to_string(encoder, len).await.unwrap();
+        encoded.split_once("\r\n\r\n").unwrap().1.to_string()
+    }
+
+    #[async_std::test]
+    async fn chunked_small_buffers() {
+        for len in 1..8 {
+            let encoder = Encoder::new(chunked_body(&["hello world"]), Method::Get);
+            assert_eq!(
+                encoded_body(encoder, len).await,
+                "B\r\nhello world\r\n0\r\n\r\n"
+            );
+        }
+    }
+
+    #[async_std::test]
+    async fn chunk_size() {
+        let encoder = Encoder::new(chunked_body(&["hello world"]), Method::Get).with_chunk_size(5);
+        assert_eq!(
+            encoded_body(encoder, 100).await,
+            "5\r\nhello\r\n5\r\n worl\r\n1\r\nd\r\n0\r\n\r\n"
+        );
+    }
+
+    #[async_std::test]
+    async fn huge_chunk_size() {
+        let encoder =
+            Encoder::new(chunked_body(&["hello world"]), Method::Get).with_chunk_size(usize::MAX);
+        assert_eq!(
+            encoded_body(encoder, 100).await,
+            "B\r\nhello world\r\n0\r\n\r\n"
+        );
+
+        let body = "x".repeat(20_000);
+        let mut res = Response::new(StatusCode::Ok);
+        res.set_body(Body::from_reader(Cursor::new(body.clone()), None));
+        let encoder = Encoder::new(res, Method::Get)
+            .with_chunk_size(usize::MAX)
+            .with_chunk_coalescing(true);
+        assert_eq!(
+            encoded_body(encoder, 1024).await,
+            format!("4E20\r\n{}\r\n0\r\n\r\n", body)
+        );
+    }
+
+    #[async_std::test]
+    async fn chunk_coalescing() {
+        let encoder = Encoder::new(chunked_body(&["hell", "o wo", "rld"]), Method::Get);
+        assert_eq!(
+            encoded_body(encoder, 100).await,
+            "4\r\nhell\r\n4\r\no wo\r\n3\r\nrld\r\n0\r\n\r\n"
+        );
+
+        let encoder = Encoder::new(chunked_body(&["hell", "o wo", "rld"]), Method::Get)
+            .with_chunk_coalescing(true);
+        assert_eq!(
+            encoded_body(encoder, 100).await,
+            "B\r\nhello world\r\n0\r\n\r\n"
+        );
+    }
+
+    /// A writer that counts the writes it receives.
+    #[derive(Default)]
+    struct CountingWriter {
+        output: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.writes += 1;
+            self.output.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_write_vectored(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            self.writes += 1;
+            for buf in bufs {
+                self.output.extend_from_slice(buf);
+            }
+            Poll::Ready(Ok(bufs.iter().map(|buf| buf.len()).sum()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[async_std::test]
+    async fn write_to_sends_small_response_in_one_write() -> Result<()> {
+        let mut res = Response::new(StatusCode::Ok);
+        res.set_body(r#"{"hello":"world"}"#);
+
+        let mut writer = CountingWriter::default();
+        let written = Encoder::new(res, Method::Get).write_to(&mut writer).await?;
+
+        assert_eq!(writer.writes, 1);
+        assert_eq!(written as usize, writer.output.len());
+        let output = String::from_utf8(writer.output)?;
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\ncontent-length: 17\r\n"));
+        assert!(output.ends_with("\r\n\r\n{\"hello\":\"world\"}"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn write_to_chunked() -> Result<()> {
+        let mut writer = CountingWriter::default();
+        Encoder::new(chunked_body(&["hello", " world"]), Method::Get)
+            .write_to(&mut writer)
+            .await?;
+
+        let output = String::from_utf8(writer.output)?;
+        assert_eq!(
+            output.split_once("\r\n\r\n").unwrap().1,
+            "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+        );
+        Ok(())
+    }
+
     #[async_std::test]
     async fn head_request_fixed_body() -> Result<()> {
         let mut res = Response::new(StatusCode::Ok);
@@ -164,4 +309,100 @@ mod server_encode {
 
         Ok(())
     }
+
+    #[async_std::test]
+    async fn trailers() -> Result<()> {
+        let mut res = Response::new(StatusCode::Ok);
+        res.set_body("hello");
+        res.insert_header("Trailer", "server-timing");
+
+        let mut trailers = Trailers::new();
+        trailers.insert("Server-Timing", "db;dur=53");
+        trailers.insert("Content-Length", "5");
+        res.send_trailers().send(trailers).await;
+
+        assert_encoded(
+            100,
+            Method::Get,
+            res,
+            vec![
+                "HTTP/1.1 200 OK",
+                "content-type: text/plain;charset=utf-8",
+                "date: {DATE}",
+                "trailer: server-timing",
+                "transfer-encoding: chunked",
+                "",
+                "5",
+                "hello",
+                "0",
+                "server-timing: db;dur=53",
+                "",
+                "",
+            ],
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn trailers_announced() -> Result<()> {
+        let mut res = Response::new(StatusCode::Ok);
+        res.set_body("hello");
+
+        let mut trailers = Trailers::new();
+        trailers.insert("Server-Timing", "db;dur=53");
+        trailers.insert("Digest", "sha-256=abc");
+        res.send_trailers().send(trailers).await;
+
+        let encoded = encode_to_string(res, 100, Method::Get).await?;
+        let lines = encoded.split("\r\n").collect::<Vec<_>>();
+        assert!(lines.contains(&"trailer: digest, server-timing"));
+        assert!(encoded.ends_with("0\r\ndigest: sha-256=abc\r\nserver-timing: db;dur=53\r\n\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn trailers_sent_after_head() -> Result<()> {
+        let mut res = Response::new(StatusCode::Ok);
+        res.set_body("hello");
+        let sender = res.send_trailers();
+
+        let mut encoder = Encoder::new(res, Method::Get);
+        let mut head = vec![0; 1024];
+        let len = encoder.read(&mut head).await?;
+        let head = String::from_utf8(head[..len].to_vec())?;
+        assert!(head.contains("transfer-encoding: chunked\r\n"));
+        assert!(!head.contains("trailer:"));
+
+        let mut trailers = Trailers::new();
+        trailers.insert("Server-Timing", "db;dur=53");
+        sender.send(trailers).await;
+
+        let rest = read_to_string(encoder, 100).await?;
+        assert!(rest.ends_with("0\r\nserver-timing: db;dur=53\r\n\r\n"));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn trailers_not_sent() -> Result<()> {
+        let mut res = Response::new(StatusCode::Ok);
+        res.set_body("hello");
+        res.insert_header("Trailer", "server-timing");
+
+        let mut trailers = Trailers::new();
+        trailers.insert("Server-Timing", "db;dur=53");
+        res.send_trailers().send(trailers).await;
+
+        let encoder = Encoder::new(res, Method::Get).with_trailers(false);
+        let encoded = read_to_string(encoder, 100).await?;
+        let lines = encoded.split("\r\n").collect::<Vec<_>>();
+        assert!(lines.contains(&"content-length: 5"));
+        assert!(!lines.iter().any(|line| line.starts_with("trailer:")));
+        assert!(encoded.ends_with("\r\n\r\nhello"));
+
+        Ok(())
+    }
 }
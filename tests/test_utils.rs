@@ -1,6 +1,7 @@
 use async_h1::{
     client::Encoder,
     server::{ConnectionStatus, Server},
+    ServerOptions,
 };
 use async_std::io::{Read as AsyncRead, Write as AsyncWrite};
 use http_types::{Request, Response, Result};
@@ -36,6 +37,15 @@ where
         }
     }
 
+    #[allow(dead_code)]
+    pub fn with_opts(f: F, opts: ServerOptions) -> Self {
+        let (client, server) = TestIO::new();
+        Self {
+            server: Server::new(server, f).with_opts(opts),
+            client,
+        }
+    }
+
     #[allow(dead_code)]
     pub async fn accept_one(&mut self) -> http_types::Result<ConnectionStatus> {
         self.server.accept_one().await